@@ -0,0 +1,68 @@
+/*
+The damper felt is modelled as an extra loop gain applied once per round trip of the string.
+While the felt rests on the string the loop gain is chosen so that the string decays by 60 dB
+in `damping_time` seconds. The gain moves smoothly between 1 and this value so that
+landing and lifting the felt does not click.
+*/
+
+const FELT_TIME_CONSTANT: f32 = 0.005;
+
+pub struct Damper {
+    gain: f32,
+    current_gain: f32,
+    smoothing: f32,
+    engaged: bool,
+}
+
+impl Damper {
+    pub fn new(note_frequency: f32, sample_rate: f32, damping_time: f32) -> Damper {
+        Damper {
+            gain: f32::powf(10.0, -3.0 / (note_frequency * damping_time)),
+            current_gain: 1.0,
+            smoothing: f32::exp(-1.0 / (FELT_TIME_CONSTANT * sample_rate)),
+            engaged: false,
+        }
+    }
+
+    pub fn engage(&mut self) {
+        self.engaged = true;
+    }
+
+    pub fn lift(&mut self) {
+        self.engaged = false;
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged
+    }
+
+    pub fn next_gain(&mut self) -> f32 {
+        let target = if self.engaged { self.gain } else { 1.0 };
+        self.current_gain = target + self.smoothing * (self.current_gain - target);
+        // f32 rounding stalls the smoothing just short of the target
+        if (self.current_gain - target).abs() < 1.0e-4 {
+            self.current_gain = target;
+        }
+        self.current_gain
+    }
+}
+
+#[test]
+fn damper_work() {
+    let mut damper = Damper::new(440.0, 44100.0, 0.2);
+    assert_eq!(1.0, damper.next_gain());
+
+    damper.engage();
+    for _ in 0..44100 {
+        damper.next_gain();
+    }
+    // 88 round trips in 0.2 s
+    let decay = f32::powf(damper.next_gain(), 88.0);
+    assert!((decay - 1.0e-3).abs() < 1.0e-5);
+
+    damper.lift();
+    for _ in 0..44100 {
+        damper.next_gain();
+    }
+    assert_eq!(1.0, damper.next_gain());
+}
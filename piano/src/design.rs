@@ -0,0 +1,410 @@
+/*
+IIR filter design
+
+Butterworth, Chebyshev (type I) and elliptic filters are designed as analog prototypes
+with the passband edge at 1 rad/s, moved to the band by the usual frequency transforms and
+mapped to the z-plane with the bilinear transform, prewarped at the band edges.
+Peaking and shelving biquads follow the same transform (R. Bristow-Johnson's cookbook).
+
+The elliptic prototype follows S. J. Orfanidis, "Lecture Notes on Elliptic Filter Design",
+with the Jacobi elliptic functions computed by Landen's transformation.
+
+High orders are fragile in direct form; run them through `sos::SosFilter`.
+*/
+
+use num_complex::Complex64;
+use num_traits::float::{Float, FloatConst};
+use num_traits::identities::Zero;
+
+use super::filter::Filter;
+
+// band edges in Hz
+#[derive(Clone, Copy, Debug)]
+pub enum Band<T> {
+    LowPass(T),
+    HighPass(T),
+    BandPass(T, T),
+}
+
+// analog zeros, poles and gain
+struct Zpk {
+    z: Vec<Complex64>,
+    p: Vec<Complex64>,
+    k: f64,
+}
+
+fn product(roots: &[Complex64], s: Complex64) -> Complex64 {
+    roots
+        .iter()
+        .fold(Complex64::new(1.0, 0.0), |h, &r| h * (s - r))
+}
+
+impl Zpk {
+    // prototype with gain `h0` at DC
+    fn with_dc_gain(z: Vec<Complex64>, p: Vec<Complex64>, h0: f64) -> Zpk {
+        let zero = Complex64::new(0.0, 0.0);
+        let k = h0 * (product(&p, zero) / product(&z, zero)).re;
+        Zpk { z, p, k }
+    }
+
+    fn lowpass(self, w: f64) -> Zpk {
+        let degree = self.p.len() as i32 - self.z.len() as i32;
+        Zpk {
+            z: self.z.iter().map(|&z| z * w).collect(),
+            p: self.p.iter().map(|&p| p * w).collect(),
+            k: self.k * w.powi(degree),
+        }
+    }
+
+    fn highpass(self, w: f64) -> Zpk {
+        let zero = Complex64::new(0.0, 0.0);
+        let k = self.k * (product(&self.z, zero) / product(&self.p, zero)).re;
+        let mut z: Vec<Complex64> = self.z.iter().map(|&z| w / z).collect();
+        z.resize(self.p.len(), zero);
+        Zpk {
+            z,
+            p: self.p.iter().map(|&p| w / p).collect(),
+            k,
+        }
+    }
+
+    fn bandpass(self, w1: f64, w2: f64) -> Zpk {
+        let w0 = (w1 * w2).sqrt();
+        let bw = w2 - w1;
+        let split = |roots: &[Complex64]| {
+            let mut split = vec![];
+            for &r in roots {
+                let r = r * bw * 0.5;
+                let d = (r * r - w0 * w0).sqrt();
+                split.push(r + d);
+                split.push(r - d);
+            }
+            split
+        };
+        let degree = self.p.len() - self.z.len();
+        let mut z = split(&self.z);
+        z.resize(z.len() + degree, Complex64::new(0.0, 0.0));
+        Zpk {
+            z,
+            p: split(&self.p),
+            k: self.k * bw.powi(degree as i32),
+        }
+    }
+
+    // s = 2 (z - 1) / (z + 1)
+    fn bilinear(self) -> Zpk {
+        let two = Complex64::new(2.0, 0.0);
+        let k = self.k * (product(&self.z, two) / product(&self.p, two)).re;
+        let mut z: Vec<Complex64> = self.z.iter().map(|&z| (two + z) / (two - z)).collect();
+        z.resize(self.p.len(), Complex64::new(-1.0, 0.0));
+        Zpk {
+            z,
+            p: self.p.iter().map(|&p| (two + p) / (two - p)).collect(),
+            k,
+        }
+    }
+
+    fn to_filter<T: Float + Zero + FloatConst>(&self, name: &str) -> Filter<T> {
+        let poly = |roots: &[Complex64]| {
+            let mut c = vec![Complex64::new(1.0, 0.0)];
+            for &r in roots {
+                c.push(Complex64::new(0.0, 0.0));
+                for i in (1..c.len()).rev() {
+                    c[i] = c[i] - r * c[i - 1];
+                }
+            }
+            c.iter().map(|x| x.re).collect::<Vec<f64>>()
+        };
+        let b: Vec<T> = poly(&self.z)
+            .iter()
+            .map(|&x| T::from(self.k * x).unwrap())
+            .collect();
+        let a: Vec<T> = poly(&self.p).iter().map(|&x| T::from(x).unwrap()).collect();
+        Filter::new(self.p.len(), a, b, name.to_string())
+    }
+}
+
+fn design<T: Float + Zero + FloatConst>(
+    prototype: Zpk,
+    band: Band<T>,
+    sample_rate: T,
+    name: &str,
+) -> Filter<T> {
+    let fs = sample_rate.to_f64().unwrap();
+    let prewarp = |f: T| 2.0 * (std::f64::consts::PI * f.to_f64().unwrap() / fs).tan();
+    let analog = match band {
+        Band::LowPass(f) => prototype.lowpass(prewarp(f)),
+        Band::HighPass(f) => prototype.highpass(prewarp(f)),
+        Band::BandPass(f1, f2) => prototype.bandpass(prewarp(f1), prewarp(f2)),
+    };
+    analog.bilinear().to_filter(name)
+}
+
+fn butterworth_prototype(order: usize) -> Zpk {
+    let n = order as f64;
+    let p = (1..(order + 1))
+        .map(|k| {
+            let theta = std::f64::consts::PI * (2.0 * k as f64 + n - 1.0) / (2.0 * n);
+            Complex64::from_polar(1.0, theta)
+        })
+        .collect();
+    Zpk::with_dc_gain(vec![], p, 1.0)
+}
+
+// gain at DC of an equiripple passband with ripple `ep`
+// `is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn ripple_dc_gain(order: usize, ep: f64) -> f64 {
+    if order % 2 == 0 {
+        1.0 / (1.0 + ep * ep).sqrt()
+    } else {
+        1.0
+    }
+}
+
+fn chebyshev_prototype(order: usize, ripple_db: f64) -> Zpk {
+    let n = order as f64;
+    let ep = (10.0_f64.powf(ripple_db / 10.0) - 1.0).sqrt();
+    let v0 = (1.0 / ep).asinh() / n;
+    let p = (1..(order + 1))
+        .map(|i| {
+            let theta = std::f64::consts::PI * (2.0 * i as f64 - 1.0) / (2.0 * n);
+            Complex64::new(-v0.sinh() * theta.sin(), v0.cosh() * theta.cos())
+        })
+        .collect();
+    Zpk::with_dc_gain(vec![], p, ripple_dc_gain(order, ep))
+}
+
+// descending Landen sequence of moduli
+fn landen(k: f64) -> Vec<f64> {
+    let mut v = vec![];
+    let mut k = k;
+    while k > 1.0e-15 && v.len() < 10 {
+        k = (k / (1.0 + (1.0 - k * k).sqrt())).powi(2);
+        v.push(k);
+    }
+    v
+}
+
+// cd(u K, k) and sn(u K, k)
+fn cde(u: Complex64, k: f64) -> Complex64 {
+    ascend((u * std::f64::consts::FRAC_PI_2).cos(), k)
+}
+
+fn sne(u: Complex64, k: f64) -> Complex64 {
+    ascend((u * std::f64::consts::FRAC_PI_2).sin(), k)
+}
+
+fn ascend(w: Complex64, k: f64) -> Complex64 {
+    landen(k)
+        .iter()
+        .rev()
+        .fold(w, |w, &v| (1.0 + v) * w / (1.0 + v * w * w))
+}
+
+// u with sn(u K, k) = w
+fn asne(w: Complex64, k: f64) -> Complex64 {
+    let v = landen(k);
+    let mut w = w;
+    let mut v1 = k;
+    for &vn in v.iter() {
+        w = w / (1.0 + (1.0 - w * w * v1 * v1).sqrt()) * 2.0 / (1.0 + vn);
+        v1 = vn;
+    }
+    1.0 - w.acos() * std::f64::consts::FRAC_2_PI
+}
+
+// `is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn elliptic_prototype(order: usize, ripple_db: f64, attenuation_db: f64) -> Zpk {
+    let n = order as f64;
+    let ep = (10.0_f64.powf(ripple_db / 10.0) - 1.0).sqrt();
+    let es = (10.0_f64.powf(attenuation_db / 10.0) - 1.0).sqrt();
+    let k1 = ep / es;
+    let u: Vec<f64> = (1..(order / 2 + 1))
+        .map(|i| (2.0 * i as f64 - 1.0) / n)
+        .collect();
+
+    // the selectivity k = passband edge / stopband edge solving the degree equation
+    let k1c = (1.0 - k1 * k1).sqrt();
+    let kc = u.iter().fold(k1c.powi(order as i32), |kc, &ui| {
+        kc * sne(Complex64::new(ui, 0.0), k1c).re.powi(4)
+    });
+    let k = (1.0 - kc * kc).sqrt();
+
+    let j = Complex64::new(0.0, 1.0);
+    let v0 = (-j * asne(j / ep, k1) / n).re;
+    let mut z = vec![];
+    let mut p = vec![];
+    for &ui in u.iter() {
+        let zero = j / (k * cde(Complex64::new(ui, 0.0), k));
+        let pole = j * cde(Complex64::new(ui, -v0), k);
+        z.push(zero);
+        z.push(zero.conj());
+        p.push(pole);
+        p.push(pole.conj());
+    }
+    if order % 2 != 0 {
+        p.push(Complex64::new((j * sne(j * v0, k)).re, 0.0));
+    }
+    Zpk::with_dc_gain(z, p, ripple_dc_gain(order, ep))
+}
+
+pub fn butterworth<T: Float + Zero + FloatConst>(
+    order: usize,
+    band: Band<T>,
+    sample_rate: T,
+) -> Filter<T> {
+    design(
+        butterworth_prototype(order),
+        band,
+        sample_rate,
+        "butterworth",
+    )
+}
+
+// `ripple_db` of passband ripple
+pub fn chebyshev<T: Float + Zero + FloatConst>(
+    order: usize,
+    ripple_db: T,
+    band: Band<T>,
+    sample_rate: T,
+) -> Filter<T> {
+    let prototype = chebyshev_prototype(order, ripple_db.to_f64().unwrap());
+    design(prototype, band, sample_rate, "chebyshev")
+}
+
+// `ripple_db` of passband ripple and at least `attenuation_db` in the stopband
+pub fn elliptic<T: Float + Zero + FloatConst>(
+    order: usize,
+    ripple_db: T,
+    attenuation_db: T,
+    band: Band<T>,
+    sample_rate: T,
+) -> Filter<T> {
+    let prototype = elliptic_prototype(
+        order,
+        ripple_db.to_f64().unwrap(),
+        attenuation_db.to_f64().unwrap(),
+    );
+    design(prototype, band, sample_rate, "elliptic")
+}
+
+fn biquad<T: Float + Zero + FloatConst>(b: [f64; 3], a: [f64; 3], name: &str) -> Filter<T> {
+    let b = b.iter().map(|&x| T::from(x / a[0]).unwrap()).collect();
+    let a = a.iter().map(|&x| T::from(x / a[0]).unwrap()).collect();
+    Filter::new(2, a, b, name.to_string())
+}
+
+// (A, cos w0, alpha)
+fn cookbook<T: Float>(frequency: T, q: T, gain_db: T, sample_rate: T) -> (f64, f64, f64) {
+    let w0 =
+        2.0 * std::f64::consts::PI * frequency.to_f64().unwrap() / sample_rate.to_f64().unwrap();
+    let amplitude = 10.0_f64.powf(gain_db.to_f64().unwrap() / 40.0);
+    (amplitude, w0.cos(), w0.sin() / (2.0 * q.to_f64().unwrap()))
+}
+
+pub fn peaking<T: Float + Zero + FloatConst>(
+    frequency: T,
+    q: T,
+    gain_db: T,
+    sample_rate: T,
+) -> Filter<T> {
+    let (amplitude, cos, alpha) = cookbook(frequency, q, gain_db, sample_rate);
+    biquad(
+        [1.0 + alpha * amplitude, -2.0 * cos, 1.0 - alpha * amplitude],
+        [1.0 + alpha / amplitude, -2.0 * cos, 1.0 - alpha / amplitude],
+        "peaking",
+    )
+}
+
+pub fn low_shelf<T: Float + Zero + FloatConst>(
+    frequency: T,
+    q: T,
+    gain_db: T,
+    sample_rate: T,
+) -> Filter<T> {
+    let (a, cos, alpha) = cookbook(frequency, q, gain_db, sample_rate);
+    let beta = 2.0 * a.sqrt() * alpha;
+    biquad(
+        [
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+        ],
+        [
+            (a + 1.0) + (a - 1.0) * cos + beta,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - beta,
+        ],
+        "low shelf",
+    )
+}
+
+pub fn high_shelf<T: Float + Zero + FloatConst>(
+    frequency: T,
+    q: T,
+    gain_db: T,
+    sample_rate: T,
+) -> Filter<T> {
+    let (a, cos, alpha) = cookbook(frequency, q, gain_db, sample_rate);
+    let beta = 2.0 * a.sqrt() * alpha;
+    biquad(
+        [
+            a * ((a + 1.0) + (a - 1.0) * cos + beta),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - beta),
+        ],
+        [
+            (a + 1.0) - (a - 1.0) * cos + beta,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - beta,
+        ],
+        "high shelf",
+    )
+}
+
+#[test]
+fn design_work() {
+    let fs: f64 = 44100.0;
+    let db = |filter: &Filter<f64>, f: f64| 20.0 * filter.response(f, fs).norm().log10();
+
+    let lowpass = butterworth(4, Band::LowPass(1000.0), fs);
+    assert!(db(&lowpass, 10.0).abs() < 1.0e-3);
+    assert!((db(&lowpass, 1000.0) + 3.0103).abs() < 1.0e-3);
+    assert!(db(&lowpass, 10000.0) < -70.0);
+
+    let highpass = butterworth(3, Band::HighPass(200.0), fs);
+    assert!((db(&highpass, 200.0) + 3.0103).abs() < 1.0e-3);
+    assert!(db(&highpass, 20000.0).abs() < 1.0e-3);
+
+    let bandpass = chebyshev(3, 0.5, Band::BandPass(500.0, 2000.0), fs);
+    assert_eq!(6, bandpass.n);
+    for &f in [500.0, 700.0, 1000.0, 1500.0, 2000.0].iter() {
+        assert!(db(&bandpass, f) > -0.5001 && db(&bandpass, f) < 1.0e-6);
+    }
+    assert!(db(&bandpass, 50.0) < -40.0);
+
+    for &order in [4, 5].iter() {
+        let lowpass = elliptic(order, 1.0, 60.0, Band::LowPass(2000.0), fs);
+        for i in 0..=20 {
+            let f = 100.0 * i as f64;
+            assert!(db(&lowpass, f) > -1.0001 && db(&lowpass, f) < 1.0e-6);
+        }
+        // equiripple stopband from a little below 5 kHz
+        for i in 0..100 {
+            let f = 5000.0 + 170.0 * i as f64;
+            assert!(db(&lowpass, f) < -59.99);
+        }
+    }
+
+    let bell = peaking(1000.0, 2.0, 6.0, fs);
+    assert!((db(&bell, 1000.0) - 6.0).abs() < 1.0e-6);
+    assert!(db(&bell, 20.0).abs() < 1.0e-2);
+    let low = low_shelf(200.0, 0.707, -9.0, fs);
+    assert!((db(&low, 1.0) + 9.0).abs() < 1.0e-3);
+    assert!(db(&low, 20000.0).abs() < 1.0e-2);
+    let high = high_shelf(5000.0, 0.707, 4.0, fs);
+    assert!((db(&high, 22050.0) - 4.0).abs() < 1.0e-3);
+    assert!(db(&high, 20.0).abs() < 1.0e-3);
+}
@@ -0,0 +1,312 @@
+/*
+Dispersion filter design
+
+A stiff string has the partials f_k = k f0 sqrt(1 + B k^2). In a loop of a pure delay L and an
+allpass H, partial k sits where the phase lag is 2 pi k:
+
+    w L + theta_H(w) = 2 pi k,    w = 2 pi f / fs
+
+so the allpass must have theta_H(w_k) = 2 pi k - w_k L at the partials.
+With H = z^-M A(z^-1) / A(z), theta_H = M w + 2 arg A, and arg A = beta is linear in
+the coefficients of A (Lang and Laakso's equation error):
+
+    sum_{i=1}^{M} a_i sin(i w + beta) = -sin(beta)
+
+which is solved in least squares, reweighted by 1 / |A|^2. The delay L is free and chosen to
+minimize the largest partial error. The allpass is returned as a cascade of
+second-order sections, which stay stable in f32.
+*/
+
+use num_traits::float::{Float, FloatConst};
+use num_traits::identities::Zero;
+
+use super::error::{finite, positive, Error};
+use super::filter::Filter;
+use super::response::{polynomial_group_delay, polynomial_response};
+use super::sos::roots;
+
+pub struct DispersionFilter<T> {
+    pub filters: Vec<Filter<T>>,
+    // the pure delay in samples the rest of the loop has to supply
+    pub delay: T,
+    // f_k = k f0 sqrt(1 + B k^2)
+    pub target_frequencies: Vec<T>,
+    // partials of the loop of `delay` and `filters`
+    pub partial_frequencies: Vec<T>,
+}
+
+impl<T: Float> DispersionFilter<T> {
+    pub fn errors_cents(&self) -> Vec<T> {
+        let cents = T::from(1200).unwrap();
+        self.partial_frequencies
+            .iter()
+            .zip(self.target_frequencies.iter())
+            .map(|(&f, &target)| cents * (f / target).log2())
+            .collect()
+    }
+
+    pub fn max_error_cents(&self) -> T {
+        self.errors_cents()
+            .iter()
+            .fold(T::zero(), |m, &e| m.max(e.abs()))
+    }
+}
+
+// denominators A of first and second order allpass sections
+fn sections(a: &[f64]) -> Option<Vec<Vec<f64>>> {
+    let poles = roots(a);
+    if poles.iter().any(|p| p.norm() >= 1.0) {
+        return None;
+    }
+    let mut sections = vec![];
+    let mut real = vec![];
+    for p in poles {
+        if p.im > 1.0e-9 {
+            sections.push(vec![1.0, -2.0 * p.re, p.norm_sqr()]);
+        } else if p.im >= -1.0e-9 {
+            real.push(p.re);
+        }
+    }
+    for pair in real.chunks(2) {
+        if pair.len() == 2 {
+            sections.push(vec![1.0, -(pair[0] + pair[1]), pair[0] * pair[1]]);
+        } else {
+            sections.push(vec![1.0, -pair[0]]);
+        }
+    }
+    Some(sections)
+}
+
+// phase lag and group delay of the cascade
+fn phase_lag(sections: &[Vec<f64>], omega: f64) -> (f64, f64) {
+    sections.iter().fold((0.0, 0.0), |(theta, tau), a| {
+        let n = (a.len() - 1) as f64;
+        (
+            theta + n * omega + 2.0 * polynomial_response(a, omega).arg(),
+            tau + n - 2.0 * polynomial_group_delay(a, omega),
+        )
+    })
+}
+
+// partial frequencies in Hz of the loop, by Newton's method from the targets
+fn partials(sections: &[Vec<f64>], delay: f64, targets: &[f64], fs: f64) -> Vec<f64> {
+    let two_pi = 2.0 * std::f64::consts::PI;
+    targets
+        .iter()
+        .enumerate()
+        .map(|(i, &f)| {
+            let mut omega = two_pi * f / fs;
+            for _ in 0..20 {
+                let (theta, tau) = phase_lag(sections, omega);
+                let step = (omega * delay + theta - two_pi * (i + 1) as f64) / (delay + tau);
+                omega -= step;
+                if step.abs() < 1.0e-13 {
+                    break;
+                }
+            }
+            omega * fs / two_pi
+        })
+        .collect()
+}
+
+// solves the linear system by Gaussian elimination with partial pivoting
+fn solve(mut m: Vec<Vec<f64>>, mut y: Vec<f64>) -> Vec<f64> {
+    let n = y.len();
+    for i in 0..n {
+        let pivot = (i..n)
+            .max_by(|&r, &s| m[r][i].abs().total_cmp(&m[s][i].abs()))
+            .unwrap();
+        m.swap(i, pivot);
+        y.swap(i, pivot);
+        for r in (i + 1)..n {
+            let factor = m[r][i] / m[i][i];
+            for c in i..n {
+                m[r][c] -= factor * m[i][c];
+            }
+            y[r] -= factor * y[i];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let sum: f64 = ((i + 1)..n).map(|c| m[i][c] * x[c]).sum();
+        x[i] = (y[i] - sum) / m[i][i];
+    }
+    x
+}
+
+// allpass denominator of `order` for the loop delay `delay`
+fn fit(order: usize, delay: f64, omegas: &[f64]) -> Vec<f64> {
+    let two_pi = 2.0 * std::f64::consts::PI;
+    let mut a = vec![0.0; order + 1];
+    a[0] = 1.0;
+    let mut weights = vec![1.0; omegas.len()];
+    for _ in 0..4 {
+        let mut normal = vec![vec![0.0; order]; order];
+        let mut rhs = vec![0.0; order];
+        for (k, (&omega, &weight)) in omegas.iter().zip(weights.iter()).enumerate() {
+            let theta = two_pi * (k + 1) as f64 - omega * delay;
+            let beta = 0.5 * (theta - order as f64 * omega);
+            let row: Vec<f64> = (1..(order + 1))
+                .map(|i| (i as f64 * omega + beta).sin())
+                .collect();
+            for i in 0..order {
+                for j in 0..order {
+                    normal[i][j] += weight * row[i] * row[j];
+                }
+                rhs[i] -= weight * row[i] * beta.sin();
+            }
+        }
+        // keeps the system solvable with fewer partials than coefficients
+        let ridge = 1.0e-9 * (0..order).map(|i| normal[i][i]).sum::<f64>().max(1.0e-300);
+        for i in 0..order {
+            normal[i][i] += ridge;
+        }
+        a[1..].copy_from_slice(&solve(normal, rhs));
+        weights = omegas
+            .iter()
+            .map(|&omega| 1.0 / polynomial_response(&a, omega).norm_sqr())
+            .collect();
+    }
+    a
+}
+
+// Allpass cascade of `order` matching the first `npartials` partials of a string of
+// inharmonicity `b` and fundamental `f0`. At least one partial must lie below the Nyquist frequency.
+pub fn design_dispersion<T: Float + Zero + FloatConst>(
+    b: T,
+    f0: T,
+    sample_rate: T,
+    order: usize,
+    npartials: usize,
+) -> Result<DispersionFilter<T>, Error> {
+    let b = finite(b, "inharmonicity")?.to_f64().unwrap();
+    let f0 = positive(f0, "fundamental frequency")?.to_f64().unwrap();
+    let fs = positive(sample_rate, "sample rate")?.to_f64().unwrap();
+    if b < 0.0 {
+        return Err(Error::InvalidParameter("inharmonicity"));
+    }
+    let two_pi = 2.0 * std::f64::consts::PI;
+    let targets: Vec<f64> = (1..(npartials + 1))
+        .map(|k| k as f64 * f0 * (1.0 + b * (k * k) as f64).sqrt())
+        .filter(|&f| f < 0.5 * fs)
+        .collect();
+    let omegas: Vec<f64> = targets.iter().map(|&f| two_pi * f / fs).collect();
+    let last = omegas.len() as f64;
+    let omega_last = match omegas.last() {
+        Some(&omega) => omega,
+        None => return Err(Error::InvalidParameter("number of partials below Nyquist")),
+    };
+
+    // The loop delay follows from the phase delay d of the allpass at the last partial,
+    // which lies between 0 and order pi / omega_last, and keeps the loop delay positive.
+    let design = |d: f64| {
+        let delay = (two_pi * last - d * omega_last) / omega_last;
+        let sections = sections(&fit(order, delay, &omegas))?;
+        let partials = partials(&sections, delay, &targets, fs);
+        let error = partials
+            .iter()
+            .zip(targets.iter())
+            .fold(0.0, |m: f64, (&f, &target)| m.max((f / target).ln().abs()));
+        if error.is_finite() {
+            Some((error, delay, sections, partials))
+        } else {
+            None
+        }
+    };
+    let error_of = |d: f64| design(d).map(|x| x.0).unwrap_or(f64::INFINITY);
+
+    let upper = (order as f64 * std::f64::consts::PI).min(two_pi * last) / omega_last;
+    let steps = 64;
+    let grid: Vec<f64> = (1..steps)
+        .map(|i| upper * i as f64 / steps as f64)
+        .collect();
+    let best = (0..grid.len())
+        .min_by(|&i, &j| error_of(grid[i]).total_cmp(&error_of(grid[j])))
+        .unwrap_or(0);
+    // golden section search between the neighbours of the best point
+    let mut low = if best > 0 { grid[best - 1] } else { 0.0 };
+    let mut high = grid.get(best + 1).copied().unwrap_or(upper);
+    let golden = 0.5 * (5.0_f64.sqrt() - 1.0);
+    for _ in 0..40 {
+        let x1 = high - golden * (high - low);
+        let x2 = low + golden * (high - low);
+        if error_of(x1) < error_of(x2) {
+            high = x2;
+        } else {
+            low = x1;
+        }
+    }
+    let d = if error_of(0.5 * (low + high)) <= error_of(grid[best]) {
+        0.5 * (low + high)
+    } else {
+        grid[best]
+    };
+
+    let to_t = |x: f64| T::from(x).unwrap();
+    let (_, delay, sections, partials) =
+        design(d).ok_or_else(|| Error::UnstableFilter(String::from("dispersion")))?;
+    let filters = sections
+        .iter()
+        .map(|a| {
+            let a: Vec<T> = a.iter().map(|&x| to_t(x)).collect();
+            let b: Vec<T> = a.iter().rev().copied().collect();
+            Filter::new(a.len() - 1, a, b, String::from("dispersion"))
+        })
+        .collect();
+    Ok(DispersionFilter {
+        filters,
+        delay: to_t(delay),
+        target_frequencies: targets.iter().map(|&f| to_t(f)).collect(),
+        partial_frequencies: partials.iter().map(|&f| to_t(f)).collect(),
+    })
+}
+
+#[test]
+fn dispersion_work() {
+    use num_complex::Complex64;
+
+    let fs: f64 = 44100.0;
+    let f0: f64 = 261.6;
+    let b: f64 = 1.75e-4;
+
+    // harmonic partials are 30 cents flat at the 20th partial
+    let harmonic = 1200.0 * (1.0 + b * 400.0).sqrt().log2();
+    assert!(harmonic > 30.0);
+
+    let low = design_dispersion(b, f0, fs, 4, 20).unwrap();
+    let high = design_dispersion(b, f0, fs, 16, 20).unwrap();
+    assert!(low.max_error_cents() < 1.0);
+    assert!(high.max_error_cents() < 0.05);
+    assert_eq!(
+        16,
+        high.filters.iter().map(|filter| filter.n).sum::<usize>()
+    );
+
+    // the loop of the returned filters has its partials where reported
+    for (k, &f) in high.partial_frequencies.iter().enumerate() {
+        let omega = 2.0 * std::f64::consts::PI * f / fs;
+        let h = high.filters.iter().fold(
+            Complex64::from_polar(1.0, -omega * high.delay),
+            |h, filter| h * filter.response(f, fs),
+        );
+        assert!(h.arg().abs() < 1.0e-6, "partial {}", k + 1);
+    }
+}
+
+#[test]
+fn dispersion_error_work() {
+    let fs: f64 = 44100.0;
+    // no partials, or none below the Nyquist frequency
+    assert!(design_dispersion(1.75e-4, 261.6, fs, 4, 0).is_err());
+    assert!(design_dispersion(1.75e-4, 30000.0, fs, 4, 20).is_err());
+    // a NaN inharmonicity
+    assert_eq!(
+        Some(Error::NotANumber("inharmonicity")),
+        design_dispersion(f64::NAN, 261.6, fs, 4, 20).err()
+    );
+    // a first order allpass cannot follow 40 strongly stretched partials
+    assert_eq!(
+        Some(Error::UnstableFilter(String::from("dispersion"))),
+        design_dispersion(1.0e-2, 27.5, fs, 1, 40).err()
+    );
+}
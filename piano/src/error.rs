@@ -0,0 +1,67 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // a filter of order n has n + 1 coefficients in `a` and in `b`
+    CoefficientLength {
+        expected: usize,
+        a: usize,
+        b: usize,
+    },
+    UnstableFilter(String),
+    // the largest gain of the filters of a string loop, which must not exceed 1
+    LoopGain(f32),
+    InvalidNote(usize),
+    // the string of `note_frequency` is shorter than its filters at `sample_rate`
+    SampleRateTooLow {
+        note_frequency: f32,
+        sample_rate: f32,
+    },
+    NotANumber(&'static str),
+    InvalidParameter(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::CoefficientLength { expected, a, b } => write!(
+                f,
+                "expected {} filter coefficients, got {} in a and {} in b",
+                expected, a, b
+            ),
+            Error::UnstableFilter(name) => write!(f, "unstable {} filter", name),
+            Error::LoopGain(gain) => write!(f, "loop filter gain {} exceeds unity", gain),
+            Error::InvalidNote(note) => write!(f, "invalid note {}", note),
+            Error::SampleRateTooLow {
+                note_frequency,
+                sample_rate,
+            } => write!(
+                f,
+                "sample rate {} is too low for a string of {} Hz",
+                sample_rate, note_frequency
+            ),
+            Error::NotANumber(name) => write!(f, "{} is not a number", name),
+            Error::InvalidParameter(name) => write!(f, "{} is out of range", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// rejects NaN and infinite values of the parameter `name`
+pub fn finite<T: num_traits::Float>(value: T, name: &'static str) -> Result<T, Error> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(Error::NotANumber(name))
+    }
+}
+
+// rejects values that are not finite and greater than zero
+pub fn positive<T: num_traits::Float>(value: T, name: &'static str) -> Result<T, Error> {
+    if finite(value, name)? > T::zero() {
+        Ok(value)
+    } else {
+        Err(Error::InvalidParameter(name))
+    }
+}
@@ -1,15 +1,22 @@
+use num_complex::Complex;
 use num_traits::float::{Float, FloatConst};
 use num_traits::identities::Zero;
 
+use super::error::Error;
+use super::response::{polynomial_group_delay, polynomial_response, FrequencyResponse};
 use super::ring_buffer::RingBuffer;
+use super::sos::roots;
 
 pub struct Filter<T> {
     pub n: usize,
     pub a: Vec<T>,
     pub b: Vec<T>,
     pub name: String,
+    // the last n + 1 inputs and outputs
     pub x: RingBuffer<T>,
     pub y: RingBuffer<T>,
+    // transposed direct form II, a[0] y = b[0] x + state[0]
+    state: Vec<T>,
 }
 
 impl<T: Clone + Copy + Float + Zero + FloatConst> Filter<T> {
@@ -23,69 +30,235 @@ impl<T: Clone + Copy + Float + Zero + FloatConst> Filter<T> {
             name,
             x: RingBuffer::new(n + 1, T::zero()),
             y: RingBuffer::new(n + 1, T::zero()),
+            state: vec![T::zero(); n],
         }
     }
 
+    // rejects mismatched lengths, coefficients that are not numbers and poles outside the unit circle
+    pub fn try_new(n: usize, a: Vec<T>, b: Vec<T>, name: String) -> Result<Filter<T>, Error> {
+        if a.len() != n + 1 || b.len() != n + 1 {
+            return Err(Error::CoefficientLength {
+                expected: n + 1,
+                a: a.len(),
+                b: b.len(),
+            });
+        }
+        for &x in a.iter().chain(b.iter()) {
+            super::error::finite(x, "filter coefficient")?;
+        }
+        if a[0] == T::zero() {
+            return Err(Error::InvalidParameter("a[0]"));
+        }
+        let filter = Filter::new(n, a, b, name);
+        if !filter.is_stable() {
+            return Err(Error::UnstableFilter(filter.name));
+        }
+        Ok(filter)
+    }
+
+    // all poles strictly inside the unit circle
+    pub fn is_stable(&self) -> bool {
+        let a: Vec<f64> = self.a.iter().map(|x| x.to_f64().unwrap()).collect();
+        roots(&a).iter().all(|p| p.norm() < 1.0)
+    }
+
+    // largest |H| over all frequencies
+    pub fn max_gain(&self) -> T {
+        max_gain(std::slice::from_ref(self))
+    }
+
     pub fn filter(&mut self, in_value: T) -> T {
-        let mut out_value: T = T::zero();
-        self.x.push(in_value);
-        for (&x_, &b_) in self.x.iter().zip(self.b.iter()) {
-            out_value = out_value + x_ * b_;
-            // println!("b: {}, x: {}", b_.to_f64().unwrap(), x_.to_f64().unwrap());
+        let mut out_value = [T::zero()];
+        self.filter_block(&[in_value], &mut out_value);
+        out_value[0]
+    }
+
+    // Transposed direct form II over a block, which `filter` runs one sample at a time,
+    // so that both give identical results.
+    pub fn filter_block(&mut self, input: &[T], output: &mut [T]) {
+        assert_eq!(input.len(), output.len());
+        let n = self.n;
+        let a = &self.a[..(n + 1)];
+        let b = &self.b[..(n + 1)];
+        let state = &mut self.state[..n];
+        for (&x, out_value) in input.iter().zip(output.iter_mut()) {
+            let y = match state.first() {
+                Some(&s) => (b[0] * x + s) / a[0],
+                None => b[0] * x / a[0],
+            };
+            for i in 1..n {
+                state[i - 1] = state[i] + b[i] * x - a[i] * y;
+            }
+            if n > 0 {
+                state[n - 1] = b[n] * x - a[n] * y;
+            }
+            *out_value = y;
         }
-        for (&y_, &a_) in self.y.iter().take(self.n).zip(self.a.iter().skip(1)) {
-            out_value = out_value - y_ * a_;
-            // println!("a: {}, y: {}", a_.to_f64().unwrap(), y_.to_f64().unwrap());
+        let last = input.len().saturating_sub(n + 1);
+        for (&x, &y) in input[last..].iter().zip(output[last..].iter()) {
+            self.x.push(x);
+            self.y.push(y);
         }
-        out_value = out_value / self.a[0];
-        self.y.push(out_value);
-        out_value
     }
 
-    pub fn groupdelay(&self, note_frequency: T, sample_frequency: T) -> T {
-        let df: T = T::from(5).unwrap();
-        let f2: T = note_frequency + df;
-        let f1: T = note_frequency - df;
-        let omega2: T = T::from(2).unwrap() * T::PI() * f2 / sample_frequency;
-        let omega1: T = T::from(2).unwrap() * T::PI() * f1 / sample_frequency;
-        (omega2 * self.phasedelay(f2, sample_frequency)
-            - omega1 * self.phasedelay(f1, sample_frequency))
-            / (omega2 - omega1)
+    pub fn response(&self, frequency: T, sample_frequency: T) -> Complex<T> {
+        let omega: T = T::from(2).unwrap() * T::PI() * frequency / sample_frequency;
+        polynomial_response(&self.b, omega) / polynomial_response(&self.a, omega)
     }
 
-    fn phasedelay(&self, note_frequency: T, sample_frequency: T) -> T {
-        let mut hn: [T; 2] = [T::zero(), T::zero()];
-        let mut hd: [T; 2] = [T::zero(), T::zero()];
-        let mut h: [T; 2] = [T::zero(), T::zero()];
+    pub fn frequency_response(
+        &self,
+        frequencies: &[T],
+        sample_frequency: T,
+    ) -> FrequencyResponse<T> {
+        FrequencyResponse::with_group_delay(
+            frequencies,
+            sample_frequency,
+            |f| self.response(f, sample_frequency),
+            |f| self.groupdelay(f, sample_frequency),
+        )
+    }
 
+    // in samples
+    pub fn groupdelay(&self, note_frequency: T, sample_frequency: T) -> T {
         let omega: T = T::from(2).unwrap() * T::PI() * note_frequency / sample_frequency;
-        for k in 0..(self.n + 1) {
-            hn[0] = hn[0] + T::cos(T::from(k).unwrap() * omega) * self.b[k];
-            hn[1] = hn[1] + T::sin(T::from(k).unwrap() * omega) * self.b[k];
+        polynomial_group_delay(&self.b, omega) - polynomial_group_delay(&self.a, omega)
+    }
+}
+
+// Largest |H| of the filters in series.
+pub fn max_gain<T: Clone + Copy + Float + Zero + FloatConst>(filters: &[Filter<T>]) -> T {
+    max_gain_by(|f| {
+        let f = T::from(f).unwrap();
+        filters.iter().fold(T::one(), |g, filter| {
+            g * filter.response(f, T::one()).norm()
+        })
+    })
+}
+
+// Largest `gain` of the frequency in units of the sample rate, searched on a grid of
+// frequencies up to 0.5 and refined by golden section search around the largest point.
+pub fn max_gain_by<T: Float, F: Fn(f64) -> T>(gain: F) -> T {
+    let steps = 512;
+    let df = 0.5 / steps as f64;
+    let best = (0..(steps + 1))
+        .max_by(|&i, &j| {
+            gain(i as f64 * df)
+                .partial_cmp(&gain(j as f64 * df))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap();
+    let mut low = (best as f64 - 1.0).max(0.0) * df;
+    let mut high = (best as f64 + 1.0).min(steps as f64) * df;
+    let golden = 0.5 * (5.0_f64.sqrt() - 1.0);
+    for _ in 0..30 {
+        let f1 = high - golden * (high - low);
+        let f2 = low + golden * (high - low);
+        if gain(f1) > gain(f2) {
+            high = f2;
+        } else {
+            low = f1;
         }
-        for k in 0..(self.n + 1) {
-            hd[0] = hd[0] + T::cos(T::from(k).unwrap() * omega) * self.a[k];
-            hd[1] = hd[1] + T::sin(T::from(k).unwrap() * omega) * self.a[k];
+    }
+    gain(best as f64 * df).max(gain(0.5 * (low + high)))
+}
+
+#[test]
+fn filter_groupdelay_work() {
+    use super::loss::loss;
+    use super::thirian::thirian;
+
+    // the analytic group delay is the slope of the phase
+    let fs: f64 = 44100.0;
+    let filters: Vec<Filter<f64>> = vec![thirian(5.3, 5), loss(27.5, 0.25, 5.85)];
+    for filter in filters {
+        for &f in [27.5, 440.0, 4000.0].iter() {
+            let df = 1.0e-3;
+            let dphase = (filter.response(f + df, fs) / filter.response(f - df, fs)).arg();
+            let slope = -dphase / (2.0 * std::f64::consts::PI * 2.0 * df / fs);
+            assert!((filter.groupdelay(f, fs) - slope).abs() < 1.0e-4);
         }
-        self.complex_divide(&hn, &hd, &mut h);
-        let arg: T = h[1].atan2(h[0]);
-        let arg: T = if arg < T::zero() {
-            arg + T::from(2).unwrap() * T::PI()
-        } else {
-            arg
-        };
+    }
+}
 
-        return arg / omega;
+#[test]
+fn filter_try_new_work() {
+    let name = String::from("test");
+    assert!(Filter::try_new(1, vec![1.0, -0.5], vec![0.5, 0.0], name.clone()).is_ok());
+    assert_eq!(
+        Some(Error::CoefficientLength {
+            expected: 2,
+            a: 2,
+            b: 3
+        }),
+        Filter::try_new(1, vec![1.0, -0.5], vec![0.5, 0.0, 0.0], name.clone()).err()
+    );
+    assert_eq!(
+        Some(Error::UnstableFilter(name.clone())),
+        Filter::try_new(2, vec![1.0, 0.0, 1.21], vec![1.0, 0.0, 0.0], name.clone()).err()
+    );
+    assert_eq!(
+        Some(Error::NotANumber("filter coefficient")),
+        Filter::try_new(1, vec![1.0, f64::NAN], vec![1.0, 0.0], name).err()
+    );
+}
+
+#[test]
+fn filter_stability_work() {
+    use super::loss::loss;
+    use super::thirian::thirian;
+
+    let allpass: Filter<f64> = thirian(5.3, 5);
+    assert!(allpass.is_stable());
+    assert!((allpass.max_gain() - 1.0).abs() < 1.0e-9);
+    let lowpass: Filter<f64> = loss(261.6, 0.25, 5.85);
+    assert!((lowpass.max_gain() - (1.0 - 0.25 / 261.6)).abs() < 1.0e-9);
+    assert!(max_gain(&[allpass, lowpass]) < 1.0);
+
+    // a resonance at a quarter of the sampling rate
+    let resonance = Filter::new(2, vec![1.0, 0.0, 0.81], vec![0.19, 0.0, 0.0], String::new());
+    assert!(resonance.is_stable());
+    assert!((resonance.max_gain() - 1.0).abs() < 1.0e-6);
+    let unstable = Filter::new(2, vec![1.0, 0.0, 1.21], vec![1.0, 0.0, 0.0], String::new());
+    assert!(!unstable.is_stable());
+}
+
+#[test]
+fn filter_block_work() {
+    use super::thirian::thirian;
+
+    let input: Vec<f64> = (0..300)
+        .map(|i| ((i * 7919) % 101) as f64 / 50.0 - 1.0)
+        .collect();
+    let mut sample_by_sample: Filter<f64> = thirian(6.4, 6);
+    let mut block: Filter<f64> = thirian(6.4, 6);
+    let mut output = vec![0.0; input.len()];
+    for (chunk, out) in input.chunks(64).zip(output.chunks_mut(64)) {
+        block.filter_block(chunk, out);
+    }
+
+    // the direct form of the difference equation
+    let (a, b) = (block.a.clone(), block.b.clone());
+    let mut y: Vec<f64> = vec![];
+    for t in 0..input.len() {
+        let mut sum = 0.0;
+        for k in 0..7 {
+            if k <= t {
+                sum += b[k] * input[t - k];
+                if k > 0 {
+                    sum -= a[k] * y[t - k];
+                }
+            }
+        }
+        y.push(sum / a[0]);
     }
 
-    fn complex_divide(&self, hn: &[T; 2], hd: &[T; 2], h: &mut [T; 2]) {
-        let magn: T = T::sqrt(hn[0] * hn[0] + hn[1] * hn[1]);
-        let argn: T = hn[1].atan2(hn[0]);
-        let magd: T = T::sqrt(hd[0] * hd[0] + hd[1] * hd[1]);
-        let argd: T = hd[1].atan2(hd[0]);
-        let mag: T = magn / magd;
-        let arg: T = argn - argd;
-        h[0] = mag * T::cos(arg);
-        h[1] = mag * T::sin(arg);
+    for t in 0..input.len() {
+        assert_eq!(sample_by_sample.filter(input[t]), output[t]);
+        assert!((y[t] - output[t]).abs() < 1.0e-9);
     }
+    // and both keep the last inputs and outputs
+    assert_eq!(input[input.len() - 1], *block.x.get(0));
+    assert_eq!(output[output.len() - 7], *block.y.get(6));
+    assert_eq!(*sample_by_sample.y.get(6), *block.y.get(6));
 }
@@ -0,0 +1,320 @@
+/*
+Fractional delays
+
+The delay lines of a string are whole samples long; the rest of the period is a fractional delay.
+
+Thiran: an allpass, flat in magnitude, with a delay exact at low frequencies. Changing the delay
+redesigns the filter in place at the same order, which disturbs its state, so it should be
+kept fixed while the string sounds.
+Lagrange: an FIR interpolator, exact in phase at low frequencies but lowpass near
+the Nyquist frequency. The window of samples is centred on the delay.
+Farrow: the same Lagrange interpolator written as a polynomial in the delay with fixed
+FIR branches, so that the delay can change every sample at no cost.
+
+None of them allocates after it is made, so that `set_delay` is safe in the audio path.
+The FIR delays keep the history for the largest delay they are made for.
+*/
+
+use num_complex::Complex;
+
+use super::filter::Filter;
+use super::response::{polynomial_group_delay, polynomial_response};
+use super::ring_buffer::RingBuffer;
+use super::thirian::{thirian, thirian_coefficients};
+
+pub trait FractionalDelay {
+    // in samples
+    fn delay(&self) -> f32;
+
+    fn set_delay(&mut self, delay: f32);
+
+    fn process(&mut self, in_value: f32) -> f32;
+
+    fn response(&self, frequency: f32, sample_rate: f32) -> Complex<f32>;
+
+    // in samples
+    fn groupdelay(&self, frequency: f32, sample_rate: f32) -> f32;
+
+    // FIR delays are always stable
+    fn is_stable(&self) -> bool {
+        true
+    }
+}
+
+fn omega(frequency: f32, sample_rate: f32) -> f32 {
+    2.0 * std::f32::consts::PI * frequency / sample_rate
+}
+
+pub struct ThiranDelay {
+    delay: f32,
+    // designed and run in f64, where the direct form of a high order keeps its poles
+    filter: Filter<f64>,
+}
+
+impl ThiranDelay {
+    // of order floor(delay), as the strings of `Piano` have always been tuned
+    pub fn new(delay: f32) -> ThiranDelay {
+        let filter = thirian(delay as f64, delay as usize);
+        ThiranDelay { delay, filter }
+    }
+}
+
+impl FractionalDelay for ThiranDelay {
+    fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    // keeps the order of `new`, and holds the delay above order - 1/2 where the allpass is stable
+    fn set_delay(&mut self, delay: f32) {
+        self.delay = delay.max(self.filter.n as f32 - 0.5);
+        thirian_coefficients(self.delay as f64, &mut self.filter.a, &mut self.filter.b);
+    }
+
+    fn process(&mut self, in_value: f32) -> f32 {
+        self.filter.filter(in_value as f64) as f32
+    }
+
+    fn response(&self, frequency: f32, sample_rate: f32) -> Complex<f32> {
+        let h = self.filter.response(frequency as f64, sample_rate as f64);
+        Complex::new(h.re as f32, h.im as f32)
+    }
+
+    fn groupdelay(&self, frequency: f32, sample_rate: f32) -> f32 {
+        self.filter.groupdelay(frequency as f64, sample_rate as f64) as f32
+    }
+
+    fn is_stable(&self) -> bool {
+        self.filter.is_stable()
+    }
+}
+
+// The taps of order N read the samples k0..k0 + N back, with the rest d = delay - k0
+// between (N - 1) / 2 and (N + 1) / 2 where the interpolation is most accurate.
+pub fn window(order: usize, delay: f32) -> (usize, f32) {
+    let centre = 0.5 * (order as f32 - 1.0);
+    let k0 = if delay > centre {
+        (delay - centre) as usize
+    } else {
+        0
+    };
+    (k0, delay - k0 as f32)
+}
+
+// h_k(d) = prod_{i != k} (d - i) / (k - i), of the order h.len() - 1, without allocating
+pub fn lagrange(d: f32, h: &mut [f32]) {
+    let n = h.len();
+    for k in 0..n {
+        h[k] = (0..n)
+            .filter(|&i| i != k)
+            .fold(1.0, |h, i| h * (d - i as f32) / (k as f32 - i as f32));
+    }
+}
+
+pub struct LagrangeDelay {
+    order: usize,
+    delay: f32,
+    max_delay: f32,
+    k0: usize,
+    h: Vec<f32>,
+    history: RingBuffer<f32>,
+}
+
+impl LagrangeDelay {
+    pub fn new(order: usize, delay: f32) -> LagrangeDelay {
+        Self::with_max_delay(order, delay, delay)
+    }
+
+    // `set_delay` holds the delay at most `max_delay`
+    pub fn with_max_delay(order: usize, delay: f32, max_delay: f32) -> LagrangeDelay {
+        let max_delay = max_delay.max(delay);
+        let (k0, d) = window(order, delay);
+        let mut h = vec![0.0; order + 1];
+        lagrange(d, &mut h);
+        LagrangeDelay {
+            order,
+            delay,
+            max_delay,
+            k0,
+            h,
+            history: RingBuffer::new(window(order, max_delay).0 + order + 1, 0.0),
+        }
+    }
+}
+
+impl FractionalDelay for LagrangeDelay {
+    fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    fn set_delay(&mut self, delay: f32) {
+        let delay = delay.min(self.max_delay);
+        let (k0, d) = window(self.order, delay);
+        self.delay = delay;
+        self.k0 = k0;
+        lagrange(d, &mut self.h);
+    }
+
+    fn process(&mut self, in_value: f32) -> f32 {
+        self.history.push(in_value);
+        self.history
+            .iter()
+            .skip(self.k0)
+            .zip(self.h.iter())
+            .fold(0.0, |y, (&x, &h)| y + h * x)
+    }
+
+    fn response(&self, frequency: f32, sample_rate: f32) -> Complex<f32> {
+        let omega = omega(frequency, sample_rate);
+        Complex::from_polar(1.0, -(self.k0 as f32) * omega) * polynomial_response(&self.h, omega)
+    }
+
+    fn groupdelay(&self, frequency: f32, sample_rate: f32) -> f32 {
+        self.k0 as f32 + polynomial_group_delay(&self.h, omega(frequency, sample_rate))
+    }
+}
+
+pub struct FarrowDelay {
+    order: usize,
+    delay: f32,
+    k0: usize,
+    d: f32,
+    max_delay: f32,
+    // h_k(d) = sum_m c[m][k] d^m
+    c: Vec<Vec<f32>>,
+    history: RingBuffer<f32>,
+}
+
+impl FarrowDelay {
+    pub fn new(order: usize, delay: f32) -> FarrowDelay {
+        Self::with_max_delay(order, delay, delay)
+    }
+
+    // `set_delay` holds the delay at most `max_delay`
+    pub fn with_max_delay(order: usize, delay: f32, max_delay: f32) -> FarrowDelay {
+        let max_delay = max_delay.max(delay);
+        // expand prod_{i != k} (d - i) / (k - i) into powers of d
+        let mut c = vec![vec![0.0; order + 1]; order + 1];
+        for k in 0..(order + 1) {
+            let mut poly: Vec<f64> = vec![1.0];
+            for i in (0..(order + 1)).filter(|&i| i != k) {
+                let scale = 1.0 / (k as f64 - i as f64);
+                let mut next = vec![0.0; poly.len() + 1];
+                for (m, &p) in poly.iter().enumerate() {
+                    next[m + 1] += p * scale;
+                    next[m] -= p * i as f64 * scale;
+                }
+                poly = next;
+            }
+            for (m, &p) in poly.iter().enumerate() {
+                c[m][k] = p as f32;
+            }
+        }
+        let (k0, d) = window(order, delay);
+        FarrowDelay {
+            order,
+            delay,
+            max_delay,
+            k0,
+            d,
+            c,
+            history: RingBuffer::new(window(order, max_delay).0 + order + 1, 0.0),
+        }
+    }
+
+    fn taps(&self) -> Vec<f32> {
+        (0..(self.order + 1))
+            .map(|k| self.c.iter().rev().fold(0.0, |h, c| h * self.d + c[k]))
+            .collect()
+    }
+}
+
+impl FractionalDelay for FarrowDelay {
+    fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    fn set_delay(&mut self, delay: f32) {
+        let delay = delay.min(self.max_delay);
+        let (k0, d) = window(self.order, delay);
+        self.delay = delay;
+        self.k0 = k0;
+        self.d = d;
+    }
+
+    // y = sum_m d^m sum_k c[m][k] x[n - k0 - k], by Horner's rule in d
+    fn process(&mut self, in_value: f32) -> f32 {
+        self.history.push(in_value);
+        let mut y = 0.0;
+        for c in self.c.iter().rev() {
+            let branch = self
+                .history
+                .iter()
+                .skip(self.k0)
+                .zip(c.iter())
+                .fold(0.0, |v, (&x, &c)| v + c * x);
+            y = y * self.d + branch;
+        }
+        y
+    }
+
+    fn response(&self, frequency: f32, sample_rate: f32) -> Complex<f32> {
+        let omega = omega(frequency, sample_rate);
+        Complex::from_polar(1.0, -(self.k0 as f32) * omega)
+            * polynomial_response(&self.taps(), omega)
+    }
+
+    fn groupdelay(&self, frequency: f32, sample_rate: f32) -> f32 {
+        self.k0 as f32 + polynomial_group_delay(&self.taps(), omega(frequency, sample_rate))
+    }
+}
+
+#[test]
+fn fractional_delay_work() {
+    let fs = 44100.0;
+    let delay = 5.3;
+    let mut delays: Vec<Box<dyn FractionalDelay>> = vec![
+        Box::new(ThiranDelay::new(delay)),
+        Box::new(LagrangeDelay::new(3, delay)),
+        Box::new(FarrowDelay::new(3, delay)),
+    ];
+    for fractional_delay in delays.iter() {
+        assert!((fractional_delay.groupdelay(100.0, fs) - delay).abs() < 1.0e-3);
+    }
+
+    // a slow sine comes out delayed by `delay`
+    let omega = 2.0 * std::f32::consts::PI * 100.0 / fs;
+    for fractional_delay in delays.iter_mut() {
+        for n in 0..400 {
+            let y = fractional_delay.process(f32::sin(omega * n as f32));
+            if n > 100 {
+                assert!((y - f32::sin(omega * (n as f32 - delay))).abs() < 1.0e-3);
+            }
+        }
+    }
+
+    // Farrow is Lagrange, also after a change of the delay beyond the first window
+    let mut lagrange = LagrangeDelay::with_max_delay(3, 1.2, 8.0);
+    let mut farrow = FarrowDelay::with_max_delay(3, 1.2, 8.0);
+    for n in 0..200 {
+        if n == 100 {
+            lagrange.set_delay(7.7);
+            farrow.set_delay(7.7);
+        }
+        let x = f32::sin(0.05 * n as f32);
+        assert!((lagrange.process(x) - farrow.process(x)).abs() < 1.0e-5);
+    }
+    assert_eq!(7.7, farrow.delay());
+    assert!((farrow.groupdelay(100.0, fs) - 7.7).abs() < 1.0e-3);
+
+    // beyond the history they are made for, the delays are held at the largest
+    let mut lagrange = LagrangeDelay::new(3, 1.2);
+    lagrange.set_delay(7.7);
+    assert_eq!(1.2, lagrange.delay());
+
+    // the Thiran allpass keeps its order, and follows a change of the delay
+    let mut thiran = ThiranDelay::new(5.3);
+    thiran.set_delay(5.8);
+    assert!((thiran.groupdelay(100.0, fs) - 5.8).abs() < 1.0e-3);
+    thiran.set_delay(1.0);
+    assert!(thiran.is_stable());
+}
@@ -1,4 +1,28 @@
-pub struct Hammer {
+/*
+The hammer is a mass hitting the string through the felt.
+The felt force depends on the compression x of the felt, and the hammer is solved together
+with the junction of the strings at the strike point: the string moves with
+(dual_force_of_input_without_hammer_force + f) / sum_of_impedance_at_junction.
+*/
+
+use super::error::{finite, positive, Error};
+
+pub trait Hammer {
+    // `k` and `p` are the stiffness and the exponent of the felt, f = k x^p at rest
+    fn set_felt(&mut self, k: f32, p: f32);
+
+    // Throws the hammer at the string with velocity `v0`. With `v0 = 0` the hammer stays at rest.
+    fn strike(&mut self, v0: f32);
+
+    fn calculate_force(
+        &mut self,
+        dual_force_of_input_without_hammer_force: f32,
+        sum_of_impedance_at_junction: f32,
+    ) -> f32;
+}
+
+// f = k (x^p + alpha d(x^p)/dt)
+pub struct PowerLawHammer {
     dt: f32,
     dti: f32,
     x: f32,
@@ -11,11 +35,12 @@ pub struct Hammer {
     f: f32,
     upprev: f32,
     alpha: f32,
+    active: bool,
 }
 
-impl Hammer {
-    pub fn new(fs: f32, m: f32, k: f32, p: f32, alpha: f32, v0: f32) -> Hammer {
-        Hammer {
+impl PowerLawHammer {
+    pub fn new(fs: f32, m: f32, k: f32, p: f32, alpha: f32, v0: f32) -> PowerLawHammer {
+        PowerLawHammer {
             dt: 1.0 / fs,
             dti: fs,
             x: 0.0,
@@ -23,46 +48,260 @@ impl Hammer {
             a: 0.0,
 
             mi: 1.0 / m,
-            k: k,
-            p: p,
+            k,
+            p,
             f: 0.0,
             upprev: 0.0,
-            alpha: alpha,
+            alpha,
+            active: v0 > 0.0,
         }
     }
 
-    pub fn calculate_force(
+    pub fn try_new(
+        fs: f32,
+        m: f32,
+        k: f32,
+        p: f32,
+        alpha: f32,
+        v0: f32,
+    ) -> Result<PowerLawHammer, Error> {
+        positive(fs, "sample rate")?;
+        positive(m, "hammer mass")?;
+        positive(k, "hammer stiffness")?;
+        positive(p, "hammer exponent")?;
+        if finite(alpha, "hammer hysteresis")? < 0.0 {
+            return Err(Error::InvalidParameter("hammer hysteresis"));
+        }
+        finite(v0, "hammer velocity")?;
+        Ok(PowerLawHammer::new(fs, m, k, p, alpha, v0))
+    }
+}
+
+impl Hammer for PowerLawHammer {
+    fn set_felt(&mut self, k: f32, p: f32) {
+        self.k = k;
+        self.p = p;
+    }
+
+    fn strike(&mut self, v0: f32) {
+        self.x = 0.0;
+        self.v = v0;
+        self.a = 0.0;
+        self.f = 0.0;
+        self.upprev = 0.0;
+        self.active = v0 > 0.0;
+    }
+
+    fn calculate_force(
         &mut self,
         dual_force_of_input_without_hammer_force: f32,
         sum_of_impedance_at_junction: f32,
     ) -> f32 {
-        let mut up: f32 = if self.x > 0.0 {
-            f32::powf(self.x, self.p)
-        } else {
-            0.0
-        };
-        let mut dupdt = (up - self.upprev) * self.dti;
-        let mut v1: f32 = 0.0;
-        let mut x1: f32 = 0.0;
-        for _ in 0..5 {
-            self.f = self.k * (up + self.alpha * dupdt);
-            if self.f < 0.0 {
-                self.f = 0.0;
+        // a hammer that has never been struck rests away from the string
+        if !self.active {
+            return 0.0;
+        }
+        // f = k (1 + alpha / dt) x1^p - k alpha / dt up_prev, solved with x1 as in StulovHammer
+        let instant_k = self.k * (1.0 + self.alpha * self.dti);
+        let relaxed_f = self.k * self.alpha * self.dti * self.upprev;
+        let force_to_x = self.dt * (self.dt * self.mi + 1.0 / sum_of_impedance_at_junction);
+        let free_x = self.x
+            + (self.v - dual_force_of_input_without_hammer_force / sum_of_impedance_at_junction)
+                * self.dt;
+
+        let mut x1 = self.x;
+        for _ in 0..20 {
+            let (f, dfdx) = if x1 > 0.0 {
+                let up = f32::powf(x1, self.p);
+                (instant_k * up - relaxed_f, instant_k * self.p * up / x1)
+            } else {
+                (-relaxed_f, 0.0)
+            };
+            let (f, dfdx) = if f > 0.0 { (f, dfdx) } else { (0.0, 0.0) };
+            let step = (x1 - free_x + f * force_to_x) / (1.0 + dfdx * force_to_x);
+            x1 -= step;
+            if step.abs() <= 1.0e-6 * x1.abs() {
+                break;
             }
-            self.a = -self.f * self.mi;
-            v1 = self.v + self.a * self.dt;
-            x1 = self.x
-                + (v1
-                    - (dual_force_of_input_without_hammer_force + self.f)
-                        / (sum_of_impedance_at_junction))
-                    * self.dt;
-            up = if x1 > 0.0 { f32::powf(x1, self.p) } else { 0.0 };
-            dupdt = (up - self.upprev) * self.dti;
         }
+
+        let up = if x1 > 0.0 { f32::powf(x1, self.p) } else { 0.0 };
+        self.f = instant_k * up - relaxed_f;
+        if self.f < 0.0 {
+            self.f = 0.0;
+        }
+        self.a = -self.f * self.mi;
+        self.v += self.a * self.dt;
+        self.x = x1;
         self.upprev = up;
-        self.v = v1;
+
+        self.f
+    }
+}
+
+/*
+Stulov's hereditary felt
+
+f(t) = q0 (x^p(t) - (epsilon / tau) \int_0^t x^p(s) exp((s - t) / tau) ds)
+
+The felt is stiffer the faster it is compressed: q0 = k for instant compression,
+relaxing towards k (1 - epsilon) for slow compression.
+The memory integral is updated exactly for x^p held over one sample.
+*/
+pub struct StulovHammer {
+    dt: f32,
+    x: f32,
+    v: f32,
+    a: f32,
+
+    mi: f32,
+    k: f32,
+    p: f32,
+    epsilon: f32,
+    tau: f32,
+    decay: f32,
+    f: f32,
+    memory: f32,
+    active: bool,
+}
+
+impl StulovHammer {
+    pub fn new(fs: f32, m: f32, k: f32, p: f32, epsilon: f32, tau: f32, v0: f32) -> StulovHammer {
+        StulovHammer {
+            dt: 1.0 / fs,
+            x: 0.0,
+            v: v0,
+            a: 0.0,
+
+            mi: 1.0 / m,
+            k,
+            p,
+            epsilon,
+            tau,
+            decay: f32::exp(-1.0 / (fs * tau)),
+            f: 0.0,
+            memory: 0.0,
+            active: v0 > 0.0,
+        }
+    }
+
+    pub fn try_new(
+        fs: f32,
+        m: f32,
+        k: f32,
+        p: f32,
+        epsilon: f32,
+        tau: f32,
+        v0: f32,
+    ) -> Result<StulovHammer, Error> {
+        positive(fs, "sample rate")?;
+        positive(m, "hammer mass")?;
+        positive(k, "hammer stiffness")?;
+        positive(p, "hammer exponent")?;
+        if !(0.0..1.0).contains(&finite(epsilon, "stulov epsilon")?) {
+            return Err(Error::InvalidParameter("stulov epsilon"));
+        }
+        positive(tau, "stulov tau")?;
+        finite(v0, "hammer velocity")?;
+        Ok(StulovHammer::new(fs, m, k, p, epsilon, tau, v0))
+    }
+
+    fn next_memory(&self, up: f32) -> f32 {
+        self.memory * self.decay + up * self.tau * (1.0 - self.decay)
+    }
+}
+
+impl Hammer for StulovHammer {
+    fn set_felt(&mut self, k: f32, p: f32) {
+        self.k = k;
+        self.p = p;
+    }
+
+    fn strike(&mut self, v0: f32) {
+        self.x = 0.0;
+        self.v = v0;
+        self.a = 0.0;
+        self.f = 0.0;
+        self.memory = 0.0;
+        self.active = v0 > 0.0;
+    }
+
+    // The felt force only grows with the compression x1 at the end of the sample, so
+    // x1 - x - (v1 - (dual_force_of_input_without_hammer_force + f) / sum_of_impedance_at_junction) dt = 0
+    // has a single root, which Newton's method finds also for the stiff treble felt.
+    fn calculate_force(
+        &mut self,
+        dual_force_of_input_without_hammer_force: f32,
+        sum_of_impedance_at_junction: f32,
+    ) -> f32 {
+        // a hammer that has never been struck rests away from the string
+        if !self.active {
+            return 0.0;
+        }
+        let instant_k = self.k * (1.0 - self.epsilon * (1.0 - self.decay));
+        let relaxed_f = self.k * self.epsilon / self.tau * self.memory * self.decay;
+        let force_to_x = self.dt * (self.dt * self.mi + 1.0 / sum_of_impedance_at_junction);
+        let free_x = self.x
+            + (self.v - dual_force_of_input_without_hammer_force / sum_of_impedance_at_junction)
+                * self.dt;
+
+        let mut x1 = self.x;
+        for _ in 0..20 {
+            let (f, dfdx) = if x1 > 0.0 {
+                let up = f32::powf(x1, self.p);
+                (instant_k * up - relaxed_f, instant_k * self.p * up / x1)
+            } else {
+                (-relaxed_f, 0.0)
+            };
+            let (f, dfdx) = if f > 0.0 { (f, dfdx) } else { (0.0, 0.0) };
+            let step = (x1 - free_x + f * force_to_x) / (1.0 + dfdx * force_to_x);
+            x1 -= step;
+            if step.abs() <= 1.0e-6 * x1.abs() {
+                break;
+            }
+        }
+
+        let up = if x1 > 0.0 { f32::powf(x1, self.p) } else { 0.0 };
+        self.f = instant_k * up - relaxed_f;
+        if self.f < 0.0 {
+            self.f = 0.0;
+        }
+        self.a = -self.f * self.mi;
+        self.v += self.a * self.dt;
         self.x = x1;
+        self.memory = self.next_memory(up);
 
         self.f
     }
 }
+
+#[test]
+fn hammer_stulov_work() {
+    let fs = 44100.0;
+    let (m, k, p) = (0.01, 2.0e9, 2.5);
+
+    // without memory the Stulov felt is the power law felt without hysteresis,
+    // up to the different solvers
+    let mut power_law = PowerLawHammer::new(fs, m, k, p, 0.0, 3.0);
+    let mut elastic = StulovHammer::new(fs, m, k, p, 0.0, 1.0e-4, 3.0);
+    let mut hereditary = StulovHammer::new(fs, m, k, p, 0.6, 1.0e-4, 3.0);
+    let mut power_law_peak: f32 = 0.0;
+    let mut hereditary_peak: f32 = 0.0;
+    let mut hereditary_end = 0;
+    for n in 0..441 {
+        let f = power_law.calculate_force(0.0, 10.0);
+        assert!((f - elastic.calculate_force(0.0, 10.0)).abs() < 1.0e-2);
+        power_law_peak = power_law_peak.max(f);
+
+        let f = hereditary.calculate_force(0.0, 10.0);
+        assert!(f.is_finite() && f >= 0.0);
+        hereditary_peak = hereditary_peak.max(f);
+        if f > 0.0 {
+            hereditary_end = n;
+        }
+    }
+    assert!(hereditary_peak > 0.0);
+    assert!(hereditary_peak != power_law_peak);
+    // the hammer has left the string
+    assert!(hereditary_end < 440);
+}
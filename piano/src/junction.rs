@@ -0,0 +1,147 @@
+/*
+Scattering junction of waveguides
+
+N waveguides of impedance Z_i meet at a point moving with the velocity v.
+With the incoming waves v_i^- and the outgoing waves v_i^+ = v - v_i^-,
+the forces balance with an external force F:
+
+F + \Sigma{Z_i(v_i^- - v_i^+)} = 0
+
+so that
+v = (F + \Sigma{2 Z_i v_i^-}) / \Sigma{Z_i}
+
+\Sigma{2 Z_i v_i^-} is the dual_force_of_input, the force the waveguides would put on a rigid point.
+A load such as a hammer finds F from the dual_force_of_input and the sum of impedance, and a load
+such as a soundboard finds v from them directly.
+*/
+
+pub struct Junction {
+    impedances: Vec<f32>,
+    // sum of impedances
+    impedance: f32,
+    incoming: Vec<f32>,
+    outgoing: Vec<f32>,
+    velocity: f32,
+}
+
+impl Junction {
+    pub fn new(impedances: Vec<f32>) -> Junction {
+        let n = impedances.len();
+        Junction {
+            impedance: impedances.iter().sum(),
+            impedances,
+            incoming: vec![0.0; n],
+            outgoing: vec![0.0; n],
+            velocity: 0.0,
+        }
+    }
+
+    pub fn nports(&self) -> usize {
+        self.impedances.len()
+    }
+
+    pub fn impedance(&self) -> f32 {
+        self.impedance
+    }
+
+    // a port of zero impedance is decoupled from the junction
+    pub fn set_impedance(&mut self, port: usize, impedance: f32) {
+        if impedance != self.impedances[port] {
+            self.impedance += impedance - self.impedances[port];
+            self.impedances[port] = impedance;
+        }
+    }
+
+    pub fn set_incoming(&mut self, port: usize, wave: f32) {
+        self.incoming[port] = wave;
+    }
+
+    pub fn dual_force_of_input(&self) -> f32 {
+        let mut dual_force_of_input = 0.0;
+        for (&z, &wave) in self.impedances.iter().zip(self.incoming.iter()) {
+            dual_force_of_input += 2.0 * z * wave;
+        }
+        dual_force_of_input
+    }
+
+    // scatters the incoming waves with an external force, and returns the velocity
+    pub fn scatter(&mut self, force: f32) -> f32 {
+        let velocity = (self.dual_force_of_input() + force) / self.impedance;
+        self.set_velocity(velocity);
+        velocity
+    }
+
+    // scatters the incoming waves at a velocity given by a load
+    pub fn set_velocity(&mut self, velocity: f32) {
+        self.velocity = velocity;
+        for (outgoing, &incoming) in self.outgoing.iter_mut().zip(self.incoming.iter()) {
+            *outgoing = velocity - incoming;
+        }
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    pub fn outgoing(&self, port: usize) -> f32 {
+        self.outgoing[port]
+    }
+
+    // the force of the waveguides on the load, dual_force_of_input - v \Sigma{Z_i}
+    pub fn force(&self) -> f32 {
+        self.dual_force_of_input() - self.impedance * self.velocity
+    }
+}
+
+#[test]
+fn junction_work() {
+    // a wave meeting two equal waveguides passes a third of itself back
+    let mut junction = Junction::new(vec![1.0, 1.0, 1.0]);
+    junction.set_incoming(0, 1.0);
+    assert!((junction.scatter(0.0) - 2.0 / 3.0).abs() < 1.0e-6);
+    assert!((junction.outgoing(0) + 1.0 / 3.0).abs() < 1.0e-6);
+    assert!((junction.outgoing(1) - 2.0 / 3.0).abs() < 1.0e-6);
+    // and keeps the power Z v^2
+    let power: f32 = (0..3).map(|i| junction.outgoing(i).powi(2)).sum();
+    assert!((power - 1.0).abs() < 1.0e-6);
+    assert!(junction.force().abs() < 1.0e-6);
+
+    // a rigid load reflects the waves inverted and takes twice their force
+    let mut rigid = Junction::new(vec![2.0]);
+    rigid.set_incoming(0, 0.5);
+    rigid.set_velocity(0.0);
+    assert_eq!(-0.5, rigid.outgoing(0));
+    assert_eq!(2.0, rigid.force());
+
+    // a decoupled port neither takes nor gives force
+    let mut decoupled = Junction::new(vec![1.0, 1.0]);
+    decoupled.set_impedance(1, 0.0);
+    decoupled.set_incoming(0, 1.0);
+    decoupled.set_incoming(1, 1.0);
+    assert!((decoupled.scatter(0.0) - 2.0).abs() < 1.0e-6);
+    assert!((decoupled.impedance() - 1.0).abs() < 1.0e-6);
+
+    // two strings joined without a force are one string, where the joint takes a sample to cross
+    use super::string::String;
+    let mut long = String::new(20, 20, vec![], vec![]);
+    let mut left = String::new(8, 8, vec![], vec![]);
+    let mut right = String::new(11, 11, vec![], vec![]);
+    let mut joint = Junction::new(vec![1.0, 1.0]);
+    long.v_at_left_to_right = 1.0;
+    left.v_at_left_to_right = 1.0;
+    for _ in 0..100 {
+        long.do_delay();
+        left.do_delay();
+        right.do_delay();
+        joint.set_incoming(0, left.v_at_right_to_right);
+        joint.set_incoming(1, right.v_at_left_to_left);
+        joint.scatter(0.0);
+        left.v_at_right_to_left = joint.outgoing(0);
+        right.v_at_left_to_right = joint.outgoing(1);
+        long.v_at_left_to_right = -long.v_at_left_to_left;
+        left.v_at_left_to_right = -left.v_at_left_to_left;
+        long.v_at_right_to_left = -long.v_at_right_to_right;
+        right.v_at_right_to_left = -right.v_at_right_to_right;
+        assert!((long.v_at_right_to_right - right.v_at_right_to_right).abs() < 1.0e-6);
+    }
+}
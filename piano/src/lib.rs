@@ -0,0 +1,22 @@
+#![allow(clippy::needless_range_loop)]
+
+pub mod damper;
+pub mod design;
+pub mod dispersion;
+pub mod error;
+pub mod filter;
+pub mod fractional_delay;
+pub mod hammer;
+pub mod junction;
+pub mod loss;
+pub mod midi;
+pub mod params;
+pub mod piano;
+pub mod piano_bank;
+pub mod render;
+pub mod response;
+pub mod ring_buffer;
+pub mod sos;
+pub mod soundboard;
+pub mod string;
+pub mod thirian;
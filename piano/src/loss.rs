@@ -1,6 +1,7 @@
 use num_traits::float::{Float, FloatConst};
 use num_traits::identities::Zero;
 
+use super::error::{finite, positive, Error};
 use super::filter::Filter;
 
 pub fn loss<T: Float + Zero + FloatConst>(f0: T, c1: T, c3: T) -> Filter<T> {
@@ -19,3 +20,177 @@ pub fn loss<T: Float + Zero + FloatConst>(f0: T, c1: T, c3: T) -> Filter<T> {
 
     Filter::new(1, a, b, String::from("loss"))
 }
+
+/*
+Loss filter from decay times
+
+A partial that passes the loss filter H once per period of the string, f0 times a second,
+decays by 60 dB in T60 = 3 / (f0 (-log10 |H(f)|)).
+The one-pole H = g (1 + a1) / (1 + a1 z^-1) has 1 / |H|^2 = alpha + beta cos(w), linear in
+alpha and beta, which are fitted to the target decays in least squares relative to the loss
+1 / |H|^2 - 1, so that every partial weighs by its relative error of T60.
+A partial of infinite T60, or one long enough that its loss rounds to 0, has no loss to weigh
+and is left out.
+The filter is passive, |H| <= 1, when alpha + beta cos(w) >= 1 at every frequency,
+that is alpha >= 1 + |beta|, which the fit is held to.
+*/
+
+// `partials` are pairs of the frequency (Hz) and T60 (s)
+pub fn loss_from_t60<T: Float + Zero + FloatConst>(
+    f0: T,
+    sample_rate: T,
+    partials: &[(T, T)],
+) -> Result<Filter<T>, Error> {
+    let f0 = positive(f0, "fundamental frequency")?.to_f64().unwrap();
+    let fs = positive(sample_rate, "sample rate")?.to_f64().unwrap();
+    if partials.is_empty() {
+        return Err(Error::InvalidParameter("number of partials"));
+    }
+    // normal equations of alpha + beta cos(w) = y
+    let (mut s00, mut s01, mut s11, mut r0, mut r1) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    let mut nlossy = 0;
+    for &(f, t60) in partials {
+        let f = finite(f, "partial frequency")?.to_f64().unwrap();
+        let t60 = t60.to_f64().unwrap();
+        if t60.is_nan() {
+            return Err(Error::NotANumber("T60"));
+        }
+        if t60 <= 0.0 {
+            return Err(Error::InvalidParameter("T60"));
+        }
+        let c = (2.0 * std::f64::consts::PI * f / fs).cos();
+        let gain = 10.0_f64.powf(-3.0 / (f0 * t60));
+        let y = 1.0 / (gain * gain);
+        // a T60 so long that the gain rounds to one is as lossless as an infinite one
+        if y - 1.0 <= 0.0 {
+            continue;
+        }
+        nlossy += 1;
+        // relative to the loss per period y - 1, that is relative to T60
+        let w = 1.0 / ((y - 1.0) * (y - 1.0));
+        s00 += w;
+        s01 += w * c;
+        s11 += w * c * c;
+        r0 += w * y;
+        r1 += w * y * c;
+    }
+    let det = s00 * s11 - s01 * s01;
+    let (alpha, beta) = if nlossy == 0 {
+        (1.0, 0.0)
+    } else if nlossy < 2 || det.abs() < 1.0e-12 * s00 * s11 {
+        (r0 / s00, 0.0)
+    } else {
+        ((r0 * s11 - r1 * s01) / det, (s00 * r1 - s01 * r0) / det)
+    };
+    let alpha = alpha.max(1.0 + beta.abs());
+
+    // a1 / (1 + a1^2) = beta / (2 alpha), with the pole inside the unit circle
+    let a1 = if beta == 0.0 {
+        0.0
+    } else {
+        (alpha - (alpha * alpha - beta * beta).max(0.0).sqrt()) / beta
+    };
+    let b0 = ((1.0 + a1 * a1) / alpha).sqrt();
+
+    let a = vec![T::one(), T::from(a1).unwrap()];
+    let b = vec![T::from(b0).unwrap(), T::zero()];
+    Filter::try_new(1, a, b, String::from("loss"))
+}
+
+// the decay curve sampled at the harmonics of f0 below the Nyquist frequency
+pub fn loss_from_decay_curve<T: Float + Zero + FloatConst, F: Fn(T) -> T>(
+    f0: T,
+    sample_rate: T,
+    npartials: usize,
+    t60: F,
+) -> Result<Filter<T>, Error> {
+    let partials: Vec<(T, T)> = (1..(npartials + 1))
+        .map(|k| f0 * T::from(k).unwrap())
+        .filter(|&f| f < sample_rate / T::from(2).unwrap())
+        .map(|f| (f, t60(f)))
+        .collect();
+    loss_from_t60(f0, sample_rate, &partials)
+}
+
+// T60 (s) the loss filter gives the partials at `frequencies`
+pub fn decay_times<T: Float + Zero + FloatConst>(
+    filter: &Filter<T>,
+    f0: T,
+    sample_rate: T,
+    frequencies: &[T],
+) -> Vec<T> {
+    frequencies
+        .iter()
+        .map(|&f| {
+            let gain = filter.response(f, sample_rate).norm();
+            -T::from(3).unwrap() / (f0 * gain.log10())
+        })
+        .collect()
+}
+
+#[test]
+fn loss_from_t60_work() {
+    let fs: f64 = 44100.0;
+    let f0: f64 = 261.6;
+
+    // the decays of the one-pole filter give back the filter
+    let filter = loss(f0, 0.25, 5.85);
+    let frequencies: Vec<f64> = (1..20).map(|k| f0 * k as f64).collect();
+    let t60 = decay_times(&filter, f0, fs, &frequencies);
+    assert!(t60.windows(2).all(|t| t[1] < t[0]));
+    let partials: Vec<(f64, f64)> = frequencies.iter().copied().zip(t60).collect();
+    let fitted = loss_from_t60(f0, fs, &partials).unwrap();
+    assert!((filter.a[1] - fitted.a[1]).abs() < 1.0e-9);
+    assert!((filter.b[0] - fitted.b[0]).abs() < 1.0e-9);
+
+    // 10 s for the fundamental falling to 1 s at 6 kHz
+    let curve = |f: f64| 10.0 / (1.0 + 9.0 * (f / 6000.0) * (f / 6000.0));
+    let fitted = loss_from_decay_curve(f0, fs, 20, curve).unwrap();
+    for (&f, &t) in frequencies
+        .iter()
+        .zip(decay_times(&fitted, f0, fs, &frequencies).iter())
+    {
+        assert!((t / curve(f)).ln().abs() < 0.03);
+    }
+}
+
+#[test]
+fn loss_from_t60_degenerate_work() {
+    let fs: f64 = 44100.0;
+    let f0: f64 = 261.6;
+    assert!(loss_from_t60(f0, fs, &[]).is_err());
+    assert_eq!(
+        Some(Error::NotANumber("T60")),
+        loss_from_t60(f0, fs, &[(f0, f64::NAN)]).err()
+    );
+
+    // a partial that never decays is left out of the fit
+    let frequencies: Vec<f64> = (1..10).map(|k| f0 * k as f64).collect();
+    let t60 = decay_times(&loss(f0, 0.25, 5.85), f0, fs, &frequencies);
+    let mut partials: Vec<(f64, f64)> = frequencies.iter().copied().zip(t60.clone()).collect();
+    partials.push((20.0 * f0, f64::INFINITY));
+    let fitted = loss_from_t60(f0, fs, &partials).unwrap();
+    for (&t, &target) in decay_times(&fitted, f0, fs, &frequencies)
+        .iter()
+        .zip(t60.iter())
+    {
+        assert!((t / target).ln().abs() < 1.0e-6);
+    }
+    // and a string that never decays is lossless
+    let lossless = loss_from_t60(f0, fs, &[(f0, f64::INFINITY)]).unwrap();
+    assert_eq!(1.0, lossless.response(f0, fs).norm());
+    // as is one whose loss rounds to nothing
+    let fitted = loss_from_t60(f0, fs, &[(f0, 1.0e17), (2.0 * f0, 5.0)]).unwrap();
+    assert!(fitted
+        .a
+        .iter()
+        .chain(fitted.b.iter())
+        .all(|x| x.is_finite()));
+
+    // decays that would extrapolate to a gain above one at DC stay passive
+    let fitted = loss_from_t60(f0, fs, &[(1000.0, 1.0e6), (2000.0, 0.1)]).unwrap();
+    for k in 0..100 {
+        let f = 0.5 * fs * k as f64 / 100.0;
+        assert!(fitted.response(f, fs).norm() <= 1.0 + 1.0e-12);
+    }
+}
@@ -1,26 +1,103 @@
-mod filter;
-mod hammer;
-mod loss;
-mod piano;
-mod ring_buffer;
-mod string;
-mod thirian;
+use piano::midi;
+use piano::params::PianoParams;
+use piano::piano_bank::PianoBank;
+use piano::render::render;
 
-use hound;
-
-fn main() {
-    let mut instrument: piano::Piano = piano::Piano::new(60, 44100.0, 5.0);
+// `samples` are interleaved frames of `channels` samples
+fn write_wav(
+    path: &str,
+    sample_rate: u32,
+    channels: u16,
+    samples: &[f32],
+    gain: f32,
+) -> Result<(), hound::Error> {
     let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: 44100,
+        channels,
+        sample_rate,
         bits_per_sample: 16,
         sample_format: hound::SampleFormat::Int,
     };
-    let mut writer = hound::WavWriter::create("out.wav", spec).unwrap();
+    let mut writer = hound::WavWriter::create(path, spec)?;
     let amplitude = i16::MAX as f32;
-    for _ in 0..44100 * 3 {
-        writer
-            .write_sample((10.0 * instrument.go() * amplitude) as i16)
-            .unwrap();
+    for &sample in samples {
+        writer.write_sample((gain * sample * amplitude) as i16)?;
+    }
+    writer.finalize()
+}
+
+fn demo(sample_rate: u32) -> Vec<f32> {
+    let mut instrument = PianoBank::new(sample_rate as f32);
+
+    // (sample, note, key down)
+    let events: [(u32, usize, bool); 6] = [
+        (0, 60, true),
+        (sample_rate / 2, 64, true),
+        (sample_rate, 67, true),
+        (sample_rate * 2, 60, false),
+        (sample_rate * 2, 64, false),
+        (sample_rate * 2, 67, false),
+    ];
+    let mut samples = vec![];
+    for t in 0..sample_rate * 3 {
+        for &(time, note, key_down) in events.iter() {
+            if time == t {
+                if key_down {
+                    instrument.note_on(note, 5.0);
+                } else {
+                    instrument.note_off(note);
+                }
+            }
+        }
+        samples.push(instrument.go());
     }
+    samples
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+// usage: piano [input.mid output.wav [--params params.toml] [--channels n]]
+fn main() {
+    let sample_rate: u32 = 44100;
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        write_wav("out.wav", sample_rate, 1, &demo(sample_rate), 10.0)
+            .unwrap_or_else(|e| exit_with(&format!("failed to write out.wav: {}", e)));
+        return;
+    }
+
+    let mut params = PianoParams::default();
+    let mut channels: u16 = 2;
+    let mut i = 3;
+    while i < args.len() {
+        let value = match args.get(i + 1) {
+            Some(value) => value,
+            None => exit_with(&format!("missing value for {}", args[i])),
+        };
+        match args[i].as_str() {
+            "--params" => {
+                params = PianoParams::load(value)
+                    .unwrap_or_else(|e| exit_with(&format!("failed to read {}: {}", value, e)));
+            }
+            "--channels" => {
+                channels = match value.parse() {
+                    Ok(channels) if channels > 0 => channels,
+                    _ => exit_with(&format!("invalid number of channels {}", value)),
+                };
+            }
+            option => exit_with(&format!("unknown option {}", option)),
+        }
+        i += 2;
+    }
+
+    let events = midi::load(&args[1])
+        .unwrap_or_else(|e| exit_with(&format!("failed to read {}: {}", args[1], e)));
+    let samples = render(&events, sample_rate, channels as usize, &params)
+        .unwrap_or_else(|e| exit_with(&format!("failed to render {}: {}", args[1], e)));
+    let peak = samples.iter().fold(0.0, |peak: f32, v| peak.max(v.abs()));
+    let gain = if peak > 0.0 { 0.9 / peak } else { 1.0 };
+    write_wav(&args[2], sample_rate, channels, &samples, gain)
+        .unwrap_or_else(|e| exit_with(&format!("failed to write {}: {}", args[2], e)));
 }
@@ -0,0 +1,289 @@
+/*
+Standard MIDI File reader.
+
+Format 0 and 1 files are merged into a single list of events with their time in seconds,
+following the tempo map. Only the events the piano responds to are kept:
+note on / note off, sustain pedal (CC64) and soft pedal (CC67) on any channel.
+*/
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MidiError {
+    Io(std::io::Error),
+    InvalidChunk,
+    UnexpectedEnd,
+    UnsupportedFormat(u16),
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiError::Io(e) => write!(f, "{}", e),
+            MidiError::InvalidChunk => write!(f, "invalid chunk"),
+            MidiError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            MidiError::UnsupportedFormat(format) => write!(f, "unsupported format {}", format),
+        }
+    }
+}
+
+impl std::error::Error for MidiError {}
+
+impl From<std::io::Error> for MidiError {
+    fn from(e: std::io::Error) -> Self {
+        MidiError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+    SustainPedal(bool),
+    SoftPedal(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedEvent {
+    pub time: f64,
+    pub event: Event,
+}
+
+enum TrackEvent {
+    Tempo(u32),
+    Piano(Event),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, i: 0 }
+    }
+
+    fn is_end(&self) -> bool {
+        self.i >= self.data.len()
+    }
+
+    fn u8(&mut self) -> Result<u8, MidiError> {
+        let x = *self.data.get(self.i).ok_or(MidiError::UnexpectedEnd)?;
+        self.i += 1;
+        Ok(x)
+    }
+
+    fn peek(&self) -> Result<u8, MidiError> {
+        self.data
+            .get(self.i)
+            .copied()
+            .ok_or(MidiError::UnexpectedEnd)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], MidiError> {
+        if self.i + n > self.data.len() {
+            return Err(MidiError::UnexpectedEnd);
+        }
+        let x = &self.data[self.i..self.i + n];
+        self.i += n;
+        Ok(x)
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiError> {
+        let x = self.bytes(4)?;
+        Ok(u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    }
+
+    // variable-length quantity
+    fn vlq(&mut self) -> Result<u32, MidiError> {
+        let mut value: u32 = 0;
+        for _ in 0..4 {
+            let x = self.u8()?;
+            value = (value << 7) | (x & 0x7f) as u32;
+            if x & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MidiError::InvalidChunk)
+    }
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<(u64, TrackEvent)>, MidiError> {
+    let mut reader = Reader::new(data);
+    let mut events = vec![];
+    let mut tick: u64 = 0;
+    let mut running_status: u8 = 0;
+    while !reader.is_end() {
+        tick += reader.vlq()? as u64;
+        let status = if reader.peek()? & 0x80 != 0 {
+            reader.u8()?
+        } else {
+            running_status
+        };
+        match status {
+            0xff => {
+                let kind = reader.u8()?;
+                let length = reader.vlq()? as usize;
+                let body = reader.bytes(length)?;
+                match kind {
+                    0x2f => break,
+                    0x51 if length == 3 => {
+                        let tempo = (body[0] as u32) << 16 | (body[1] as u32) << 8 | body[2] as u32;
+                        events.push((tick, TrackEvent::Tempo(tempo)));
+                    }
+                    _ => {}
+                }
+            }
+            0xf0 | 0xf7 => {
+                let length = reader.vlq()? as usize;
+                reader.bytes(length)?;
+            }
+            0x80..=0xef => {
+                running_status = status;
+                let data1 = reader.u8()?;
+                let data2 = if status & 0xe0 == 0xc0 {
+                    0
+                } else {
+                    reader.u8()?
+                };
+                let event = match status & 0xf0 {
+                    0x80 => Some(Event::NoteOff { note: data1 }),
+                    0x90 if data2 == 0 => Some(Event::NoteOff { note: data1 }),
+                    0x90 => Some(Event::NoteOn {
+                        note: data1,
+                        velocity: data2,
+                    }),
+                    0xb0 if data1 == 64 => Some(Event::SustainPedal(data2 >= 64)),
+                    0xb0 if data1 == 67 => Some(Event::SoftPedal(data2 >= 64)),
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    events.push((tick, TrackEvent::Piano(event)));
+                }
+            }
+            _ => return Err(MidiError::InvalidChunk),
+        }
+    }
+    Ok(events)
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<TimedEvent>, MidiError> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(4)? != b"MThd" {
+        return Err(MidiError::InvalidChunk);
+    }
+    let header_length = reader.u32()? as usize;
+    let header = reader.bytes(header_length)?;
+    if header_length < 6 {
+        return Err(MidiError::InvalidChunk);
+    }
+    let format = u16::from_be_bytes([header[0], header[1]]);
+    let ntracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if format > 1 {
+        return Err(MidiError::UnsupportedFormat(format));
+    }
+
+    // (tick, track, order in track, event); the sort keeps simultaneous events in file order
+    let mut events: Vec<(u64, usize, usize, TrackEvent)> = vec![];
+    let mut track = 0;
+    while track < ntracks as usize && !reader.is_end() {
+        let kind = reader.bytes(4)?;
+        let length = reader.u32()? as usize;
+        let body = reader.bytes(length)?;
+        if kind != b"MTrk" {
+            continue;
+        }
+        for (i, (tick, event)) in parse_track(body)?.into_iter().enumerate() {
+            events.push((tick, track, i, event));
+        }
+        track += 1;
+    }
+    events.sort_by_key(|&(tick, track, i, _)| (tick, track, i));
+
+    // seconds per tick, and the tempo map for metrical time
+    let smpte = division & 0x8000 != 0;
+    let mut seconds_per_tick = if smpte {
+        // the upper byte is the negative frame rate
+        let fps = -((division >> 8) as u8 as i8) as f64;
+        let fps = if fps == 29.0 { 29.97 } else { fps };
+        1.0 / (fps * (division & 0xff) as f64)
+    } else {
+        0.5 / division as f64
+    };
+
+    let mut timed_events = vec![];
+    let mut last_tick: u64 = 0;
+    let mut time: f64 = 0.0;
+    for (tick, _, _, event) in events {
+        time += (tick - last_tick) as f64 * seconds_per_tick;
+        last_tick = tick;
+        match event {
+            TrackEvent::Tempo(tempo) => {
+                if !smpte {
+                    seconds_per_tick = tempo as f64 * 1.0e-6 / division as f64;
+                }
+            }
+            TrackEvent::Piano(event) => timed_events.push(TimedEvent { time, event }),
+        }
+    }
+    Ok(timed_events)
+}
+
+pub fn load(path: &str) -> Result<Vec<TimedEvent>, MidiError> {
+    parse(&std::fs::read(path)?)
+}
+
+#[test]
+fn midi_parse_work() {
+    fn chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = kind.to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    // format 1, 2 tracks, 96 ticks per quarter note
+    let mut data = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+    // 120 bpm, then 60 bpm after one beat
+    data.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // tempo 500000
+            0x60, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40, // tempo 1000000
+            0x00, 0xff, 0x2f, 0x00,
+        ],
+    ));
+    // running status and a note on of velocity 0 as note off
+    data.extend(chunk(
+        b"MTrk",
+        &[
+            0x00, 0xb0, 0x40, 0x7f, // sustain on
+            0x00, 0x90, 0x3c, 0x64, // note on
+            0x81, 0x40, 0x3c, 0x00, // note off after 192 ticks
+            0x60, 0xb0, 0x43, 0x7f, // soft on
+            0x00, 0xc0, 0x00, // program change
+            0x00, 0xff, 0x2f, 0x00,
+        ],
+    ));
+
+    let events = parse(&data).unwrap();
+    let expected = [
+        (0.0, Event::SustainPedal(true)),
+        (
+            0.0,
+            Event::NoteOn {
+                note: 60,
+                velocity: 100,
+            },
+        ),
+        (1.5, Event::NoteOff { note: 60 }),
+        (2.5, Event::SoftPedal(true)),
+    ];
+    assert_eq!(expected.len(), events.len());
+    for (&(time, event), timed_event) in expected.iter().zip(events.iter()) {
+        assert!((time - timed_event.time).abs() < 1.0e-9);
+        assert_eq!(event, timed_event.event);
+    }
+}
@@ -0,0 +1,293 @@
+/*
+Physical constants of the instrument.
+
+Most constants vary along the keyboard through the position
+x = ln(f / 27.5) / ln(4192 / 27.5), which is 0 at A0 and about 1 at C8.
+Single keys can be voiced by the override table `keys`.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Io(std::io::Error),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
+    Json(serde_json::Error),
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamsError::Io(e) => write!(f, "{}", e),
+            ParamsError::TomlDe(e) => write!(f, "{}", e),
+            ParamsError::TomlSer(e) => write!(f, "{}", e),
+            ParamsError::Json(e) => write!(f, "{}", e),
+            ParamsError::UnknownFormat(path) => {
+                write!(f, "{}: expected a .toml or .json file", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+impl From<std::io::Error> for ParamsError {
+    fn from(e: std::io::Error) -> Self {
+        ParamsError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ParamsError {
+    fn from(e: toml::de::Error) -> Self {
+        ParamsError::TomlDe(e)
+    }
+}
+
+impl From<toml::ser::Error> for ParamsError {
+    fn from(e: toml::ser::Error) -> Self {
+        ParamsError::TomlSer(e)
+    }
+}
+
+impl From<serde_json::Error> for ParamsError {
+    fn from(e: serde_json::Error) -> Self {
+        ParamsError::Json(e)
+    }
+}
+
+// the keys of the keyboard, A0 to C8
+pub const LOWEST_NOTE: usize = 21;
+pub const HIGHEST_NOTE: usize = 108;
+pub const NUM_KEYS: usize = HIGHEST_NOTE - LOWEST_NOTE + 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HammerModel {
+    PowerLaw,
+    Stulov,
+}
+
+// the fractional delay that tunes each string, see `fractional_delay`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FractionalDelayKind {
+    Thiran,
+    Lagrange,
+    Farrow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PianoParams {
+    // string density (kg/m^3) and Young's modulus (Pa)
+    pub rho: f32,
+    pub e: f32,
+    // strike point as a fraction of the speaking length
+    pub hammer_position: f32,
+    // loss filter constants, see `loss::loss`
+    pub lowpass_c1: f32,
+    pub lowpass_c3: f32,
+    // detuning of the unison strings
+    pub tune: Vec<f32>,
+    pub two_strings_from: usize,
+    pub three_strings_from: usize,
+
+    // m = hammer_mass_bass - hammer_mass_decrease * x^0.1
+    pub hammer_mass_bass: f32,
+    pub hammer_mass_decrease: f32,
+    // p = hammer_exponent_bass + hammer_exponent_increase * x
+    pub hammer_exponent_bass: f32,
+    pub hammer_exponent_increase: f32,
+    // k is chosen so that the felt pushes with hammer_force at hammer_compression
+    pub hammer_force: f32,
+    pub hammer_compression: f32,
+    // alpha = hammer_hysteresis * x
+    pub hammer_hysteresis: f32,
+    // felt met with una corda
+    pub soft_hammer_exponent_shift: f32,
+    pub soft_hammer_force: f32,
+    // hereditary amplitude and relaxation time (s) of the Stulov felt
+    pub hammer_model: HammerModel,
+    pub stulov_epsilon: f32,
+    pub stulov_tau: f32,
+
+    // the order applies to Lagrange and Farrow, Thiran takes the order of its delay
+    pub fractional_delay: FractionalDelayKind,
+    pub fractional_delay_order: usize,
+    // large strikes stretch the strings and briefly raise the pitch
+    pub tension_modulation: bool,
+    // A second, horizontal polarisation of each string, tuned by horizontal_tune with lowpass_c1
+    // and lowpass_c3 scaled by horizontal_loss. The hammer strikes the vertical one only.
+    pub dual_polarisation: bool,
+    pub horizontal_tune: f32,
+    pub horizontal_loss: f32,
+    // bridge admittance (m / N s) from the forces of the vertical and horizontal polarisations
+    // to the velocities of the bridge, the vertical one in series with the soundboard
+    // The bridge has to be passive: with the admittance of the soundboard added to the vertical
+    // one, the symmetric part of the matrix is positive semidefinite.
+    pub bridge_admittance: [[f32; 2]; 2],
+    // longitudinal waves driven by the stretching of the strings, for the phantom partials of the bass
+    pub longitudinal: bool,
+    // the part of the longitudinal force that moves the soundboard, through the angle of the strings
+    // over the bridge
+    pub longitudinal_coupling: f32,
+
+    // damping_time = damping_time_bass - damping_time_decrease * x
+    pub damping_time_bass: f32,
+    pub damping_time_decrease: f32,
+    pub undamped_from: usize,
+
+    pub keys: Vec<KeyParams>,
+}
+
+// Overrides for a single key. Unset values follow the curves of `PianoParams`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct KeyParams {
+    pub note: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hammer_position: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lowpass_c1: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lowpass_c3: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tune: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nstrings: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hammer_mass: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hammer_stiffness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hammer_exponent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub damping_time: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fractional_delay: Option<FractionalDelayKind>,
+}
+
+impl Default for PianoParams {
+    fn default() -> Self {
+        PianoParams {
+            rho: 7850.0,
+            e: 200.0e9,
+            hammer_position: 1.0 / 7.0,
+            lowpass_c1: 0.25,
+            lowpass_c3: 5.85,
+            tune: vec![1.0, 1.0003, 0.9996],
+            two_strings_from: 31,
+            three_strings_from: 41,
+
+            hammer_mass_bass: 0.06,
+            hammer_mass_decrease: 0.058,
+            hammer_exponent_bass: 2.0,
+            hammer_exponent_increase: 1.0,
+            hammer_force: 40.0,
+            hammer_compression: 0.7e-3,
+            hammer_hysteresis: 0.1e-4,
+            soft_hammer_exponent_shift: -0.2,
+            soft_hammer_force: 25.0,
+            hammer_model: HammerModel::PowerLaw,
+            stulov_epsilon: 0.6,
+            stulov_tau: 1.0e-4,
+
+            fractional_delay: FractionalDelayKind::Thiran,
+            fractional_delay_order: 3,
+            tension_modulation: false,
+            dual_polarisation: false,
+            horizontal_tune: 1.0002,
+            horizontal_loss: 0.8,
+            bridge_admittance: [[0.0, 2.0e-4], [2.0e-4, 5.0e-4]],
+            longitudinal: false,
+            longitudinal_coupling: 0.02,
+
+            damping_time_bass: 0.5,
+            damping_time_decrease: 0.4,
+            undamped_from: 89,
+
+            keys: vec![],
+        }
+    }
+}
+
+impl PianoParams {
+    pub fn key(&self, note: usize) -> Option<&KeyParams> {
+        self.keys.iter().find(|key| key.note == note)
+    }
+
+    pub fn from_toml(s: &str) -> Result<PianoParams, ParamsError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ParamsError> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<PianoParams, ParamsError> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, ParamsError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // the format follows the extension, .toml or .json
+    pub fn load(path: &str) -> Result<PianoParams, ParamsError> {
+        let s = std::fs::read_to_string(path)?;
+        if path.ends_with(".toml") {
+            Self::from_toml(&s)
+        } else if path.ends_with(".json") {
+            Self::from_json(&s)
+        } else {
+            Err(ParamsError::UnknownFormat(path.to_string()))
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ParamsError> {
+        let s = if path.ends_with(".toml") {
+            self.to_toml()?
+        } else if path.ends_with(".json") {
+            self.to_json()?
+        } else {
+            return Err(ParamsError::UnknownFormat(path.to_string()));
+        };
+        std::fs::write(path, s)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn params_work() {
+    let params = PianoParams {
+        hammer_model: HammerModel::Stulov,
+        dual_polarisation: true,
+        bridge_admittance: [[0.0, 1.0e-4], [1.0e-4, 1.0e-3]],
+        keys: vec![KeyParams {
+            note: 60,
+            hammer_position: Some(0.12),
+            tune: Some(vec![1.0, 1.001, 0.999]),
+            fractional_delay: Some(FractionalDelayKind::Farrow),
+            ..KeyParams::default()
+        }],
+        ..PianoParams::default()
+    };
+
+    let toml = params.to_toml().unwrap();
+    assert_eq!(params, PianoParams::from_toml(&toml).unwrap());
+    let json = params.to_json().unwrap();
+    assert_eq!(params, PianoParams::from_json(&json).unwrap());
+
+    // missing values take their defaults
+    let partial =
+        PianoParams::from_toml("rho = 8000.0\n[[keys]]\nnote = 21\nnstrings = 2\n").unwrap();
+    assert_eq!(8000.0, partial.rho);
+    assert_eq!(5.85, partial.lowpass_c3);
+    assert_eq!(HammerModel::PowerLaw, partial.hammer_model);
+    assert_eq!(FractionalDelayKind::Thiran, partial.fractional_delay);
+    assert_eq!(Some(2), partial.key(21).unwrap().nstrings);
+    assert!(partial.key(22).is_none());
+}
@@ -1,7 +1,20 @@
-use super::hammer::Hammer;
+use super::damper::Damper;
+use super::error::{finite, positive, Error};
+use super::fractional_delay::{FarrowDelay, FractionalDelay, LagrangeDelay, ThiranDelay};
+use super::hammer::{Hammer, PowerLawHammer, StulovHammer};
+use super::junction::Junction;
 use super::loss::loss;
-use super::string::String;
-use super::thirian::{thirian, thirian_dispersion};
+use super::params::{FractionalDelayKind, HammerModel, PianoParams, HIGHEST_NOTE, LOWEST_NOTE};
+use super::soundboard::{ModalSoundboard, Soundboard};
+use super::string::{String, MAX_LENGTH_SCALE};
+use super::thirian::thirian_dispersion;
+
+// samples over which the strings reach a new pitch, and between updates of tension modulation
+const PITCH_RAMP: usize = 64;
+// the most tension modulation raises the pitch, a semitone
+const MAX_TENSION_RISE: f32 = 0.06;
+// segments of a longitudinal wave, each driven by the stretching of the transverse string over it
+const LONGITUDINAL_SEGMENTS: usize = 16;
 
 /*
 F+ \Sigma{Z_i(v_i^- - v_i^+}) = 0
@@ -16,92 +29,319 @@ and output is determined by this `v`.
 */
 
 pub struct Piano {
-    string_impedance: f32,
-    soundboard_impedance: f32,
+    soundboard: Box<dyn Soundboard>,
 
     nstrings: usize,
     left_strings: Vec<String>,
     right_strings: Vec<String>,
-    hammers: Vec<Hammer>,
+    // in samples, of each unison string
+    periods: Vec<f32>,
+    // the horizontal polarisation of each unison string, empty with a single polarisation
+    horizontal_left_strings: Vec<String>,
+    horizontal_right_strings: Vec<String>,
+    horizontal_periods: Vec<f32>,
+    bridge_admittance: [[f32; 2]; 2],
+    // forces of the strings on the bridge, vertical and horizontal, from the last sample
+    bridge_force: [f32; 2],
+    // the strings meet the soundboard at the bridge, and each other at the hammer
+    bridge: Junction,
+    horizontal_bridge: Option<Junction>,
+    hammer_junctions: Vec<Junction>,
+    // the longitudinal wave of each unison string, empty when disabled or above the Nyquist frequency
+    longitudinal_strings: Vec<String>,
+    longitudinal_impedance: f32,
+    longitudinal_coupling: f32,
+    // E A / 2 of the core, the force of the squared slope
+    longitudinal_excitation: f32,
+    // of the transverse waves, in m/s
+    wave_speed: f32,
+    pitch_bend: f32,
+    // relative rise of the pitch per mean square velocity of the strings, 0 without tension modulation
+    tension_modulation: f32,
+    // samples to the next update of tension modulation
+    tension_update: usize,
+    // the relative rise of the pitch of each unison string, which may grow only while its
+    // strike builds up
+    tension_rise: Vec<f32>,
+    tension_attack: Vec<bool>,
+    hammers: Vec<Box<dyn Hammer>>,
+    damper: Option<Damper>,
+
+    hammer_k: f32,
+    hammer_p: f32,
+    soft_hammer_k: f32,
+    soft_hammer_p: f32,
+    soft_pedal: bool,
 }
 
 impl Piano {
     pub fn new(note: usize, sample_rate: f32, v0: f32) -> Piano {
+        Self::with_params(note, sample_rate, v0, &PianoParams::default())
+    }
+
+    // Accepts notes beyond the keyboard, and panics where the other checks of `try_with_params` fail.
+    pub fn with_params(note: usize, sample_rate: f32, v0: f32, params: &PianoParams) -> Piano {
+        Self::build(note, sample_rate, v0, params).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(note: usize, sample_rate: f32, v0: f32) -> Result<Piano, Error> {
+        Self::try_with_params(note, sample_rate, v0, &PianoParams::default())
+    }
+
+    pub fn try_with_params(
+        note: usize,
+        sample_rate: f32,
+        v0: f32,
+        params: &PianoParams,
+    ) -> Result<Piano, Error> {
+        if !(LOWEST_NOTE..=HIGHEST_NOTE).contains(&note) {
+            return Err(Error::InvalidNote(note));
+        }
+        Self::build(note, sample_rate, v0, params)
+    }
+
+    fn build(note: usize, sample_rate: f32, v0: f32, params: &PianoParams) -> Result<Piano, Error> {
+        positive(sample_rate, "sample rate")?;
+        if finite(v0, "hammer velocity")? < 0.0 {
+            return Err(Error::InvalidParameter("hammer velocity"));
+        }
+        positive(params.rho, "rho")?;
+        positive(params.e, "e")?;
+
         let note_frequency: f32 = 440.0 * f32::powf(2.0, (note as f32 - 69.0) / 12.0);
+        let key = params.key(note);
 
         let f0 = 27.5;
-        let rho = 7850.0;
-        let p = 2.0 + 1.0 * f32::ln(note_frequency / f0) / f32::ln(4192.0 / f0);
-        let m = 0.06 - 0.058 * f32::powf(f32::ln(note_frequency / f0) / f32::ln(4192.0 / f0), 0.1);
-        let k = 40.0 / f32::powf(0.7e-3, p);
+        let x = f32::ln(note_frequency / f0) / f32::ln(4192.0 / f0);
+        let rho = params.rho;
+        let p = key
+            .and_then(|key| key.hammer_exponent)
+            .unwrap_or(params.hammer_exponent_bass + params.hammer_exponent_increase * x);
+        let m = key
+            .and_then(|key| key.hammer_mass)
+            .unwrap_or(params.hammer_mass_bass - params.hammer_mass_decrease * f32::powf(x, 0.1));
+        let k = key
+            .and_then(|key| key.hammer_stiffness)
+            .unwrap_or(params.hammer_force / f32::powf(params.hammer_compression, p));
+        // with una corda the strings meet the less compressed felt at the side of the hammer
+        let soft_p = p + params.soft_hammer_exponent_shift;
+        let soft_k = k * params.soft_hammer_force / params.hammer_force
+            * f32::powf(params.hammer_compression, p - soft_p);
         let l = 0.04 + 1.4 / (1.0 + f32::exp(-3.4 + 1.4 * f32::ln(note_frequency / f0)));
         let r = 0.002 * f32::powf(1.0 + 0.6 * f32::ln(note_frequency / f0), -1.4);
         let rho_l = std::f32::consts::PI * r * r * rho;
         let t = (2.0 * l * note_frequency) * (2.0 * l * note_frequency) * rho_l;
-        let e = 200.0e9;
+        let e = params.e;
 
         let rcore = if r < 0.0006 { r } else { 0.0006 };
         let thirian_b = (std::f32::consts::PI * std::f32::consts::PI * std::f32::consts::PI)
             * e
             * (rcore * rcore * rcore * rcore)
             / (4.0 * l * l * t);
-        let hammer_position = 1.0 / 7.0;
+        let hammer_position = key
+            .and_then(|key| key.hammer_position)
+            .unwrap_or(params.hammer_position);
         let string_impedance = f32::sqrt(t * rho_l);
-        let soundboard_impedance = 4000.0;
-
-        println!(
-            "note_frequency = {}, r = {} mm, L = {}, T = {}, hammer_position = {}, string_impedance = {}, k = {}, thirian_b = {}",
-            note_frequency,
-            1000.0 * r,
-            l,
-            t,
-            hammer_position,
-            string_impedance,
-            k,
-            thirian_b,
-        );
 
-        let lowpass_c1 = 0.25;
-        let lowpass_c3 = 5.85;
-        let nstrings: usize = if note < 31 {
-            1
-        } else if note < 41 {
-            2
-        } else {
-            3
-        };
-        const TUNE: [f32; 3] = [1.0, 1.0003, 0.9996];
+        let lowpass_c1 = key
+            .and_then(|key| key.lowpass_c1)
+            .unwrap_or(params.lowpass_c1);
+        let lowpass_c3 = key
+            .and_then(|key| key.lowpass_c3)
+            .unwrap_or(params.lowpass_c3);
+        if !(0.0..1.0).contains(&positive(hammer_position, "hammer position")?) {
+            return Err(Error::InvalidParameter("hammer position"));
+        }
+        finite(lowpass_c1, "lowpass c1")?;
+        positive(lowpass_c3, "lowpass c3")?;
+        let nstrings: usize =
+            key.and_then(|key| key.nstrings)
+                .unwrap_or(if note < params.two_strings_from {
+                    1
+                } else if note < params.three_strings_from {
+                    2
+                } else {
+                    3
+                });
+        if nstrings == 0 {
+            return Err(Error::InvalidParameter("nstrings"));
+        }
+        let tune = key
+            .and_then(|key| key.tune.as_ref())
+            .unwrap_or(&params.tune);
+        let fractional_delay = key
+            .and_then(|key| key.fractional_delay)
+            .unwrap_or(params.fractional_delay);
+        if params.fractional_delay_order == 0 {
+            return Err(Error::InvalidParameter("fractional delay order"));
+        }
         let mut left_strings = vec![];
         let mut right_strings = vec![];
+        let mut periods = vec![];
         for i in 0..nstrings {
+            let string_frequency =
+                note_frequency * positive(tune.get(i).copied().unwrap_or(1.0), "tune")?;
+            periods.push(sample_rate / string_frequency);
             let (ls, rs) = Self::new_string(
-                note_frequency * TUNE[i],
+                string_frequency,
                 sample_rate,
                 hammer_position,
                 lowpass_c1,
                 lowpass_c3,
                 thirian_b,
-            );
+                fractional_delay,
+                params.fractional_delay_order,
+            )?;
             left_strings.push(ls);
             right_strings.push(rs);
         }
 
-        let alpha = 0.1e-4 * f32::ln(note_frequency / f0) / f32::ln(4192.0 / f0);
+        let soundboard = ModalSoundboard::piano(sample_rate);
+        let mut horizontal_left_strings = vec![];
+        let mut horizontal_right_strings = vec![];
+        let mut horizontal_periods = vec![];
+        if params.dual_polarisation {
+            positive(params.horizontal_tune, "horizontal tune")?;
+            positive(params.horizontal_loss, "horizontal loss")?;
+            for row in params.bridge_admittance.iter() {
+                for &y in row.iter() {
+                    finite(y, "bridge admittance")?;
+                }
+            }
+            // A passive bridge gives no energy back to the strings: the symmetric part of the
+            // admittance, with the soundboard in series with the vertical polarisation, is
+            // positive semidefinite.
+            let y = params.bridge_admittance;
+            let vertical = y[0][0] + 1.0 / soundboard.impedance();
+            let coupling = 0.5 * (y[0][1] + y[1][0]);
+            if vertical < 0.0 || y[1][1] < 0.0 || vertical * y[1][1] < coupling * coupling {
+                return Err(Error::InvalidParameter("bridge admittance"));
+            }
+            for &period in periods.iter() {
+                let string_frequency = sample_rate / period * params.horizontal_tune;
+                horizontal_periods.push(sample_rate / string_frequency);
+                let (ls, rs) = Self::new_string(
+                    string_frequency,
+                    sample_rate,
+                    hammer_position,
+                    lowpass_c1 * params.horizontal_loss,
+                    lowpass_c3 * params.horizontal_loss,
+                    thirian_b,
+                    fractional_delay,
+                    params.fractional_delay_order,
+                )?;
+                horizontal_left_strings.push(ls);
+                horizontal_right_strings.push(rs);
+            }
+        }
+
+        let alpha = params.hammer_hysteresis * x;
 
-        let mut hammers: Vec<Hammer> = vec![];
+        let mut hammers: Vec<Box<dyn Hammer>> = vec![];
         for _ in 0..nstrings {
-            hammers.push(Hammer::new(sample_rate, m, k, p, alpha, v0));
+            hammers.push(match params.hammer_model {
+                HammerModel::PowerLaw => {
+                    Box::new(PowerLawHammer::try_new(sample_rate, m, k, p, alpha, v0)?)
+                }
+                HammerModel::Stulov => Box::new(StulovHammer::try_new(
+                    sample_rate,
+                    m,
+                    k,
+                    p,
+                    params.stulov_epsilon,
+                    params.stulov_tau,
+                    v0,
+                )?),
+            });
         }
-        Piano {
-            string_impedance,
-            soundboard_impedance,
+
+        // the top keys of a piano have no dampers
+        let damper = if note < params.undamped_from {
+            let damping_time = key
+                .and_then(|key| key.damping_time)
+                .unwrap_or(params.damping_time_bass - params.damping_time_decrease * x);
+            positive(damping_time, "damping time")?;
+            Some(Damper::new(note_frequency, sample_rate, damping_time))
+        } else {
+            None
+        };
+
+        positive(soft_k, "soft hammer stiffness")?;
+        positive(soft_p, "soft hammer exponent")?;
+
+        // The longitudinal waves travel at sqrt(E A / rho_l), an order of magnitude faster than the
+        // transverse ones, and end at the bridge and the agraffe as rigid terminations.
+        let ea = e * std::f32::consts::PI * rcore * rcore;
+        let longitudinal_frequency = f32::sqrt(ea / rho_l) / (2.0 * l);
+        let mut longitudinal_strings = vec![];
+        if params.longitudinal {
+            finite(params.longitudinal_coupling, "longitudinal coupling")?;
+            let lowpass = loss(longitudinal_frequency, lowpass_c1, lowpass_c3);
+            let lowpass_delay = lowpass.groupdelay(longitudinal_frequency, sample_rate);
+            let del = (0.5 * (sample_rate / longitudinal_frequency - lowpass_delay)) as usize;
+            if del >= 2 {
+                for _ in 0..nstrings {
+                    longitudinal_strings.push(String::try_new(
+                        del,
+                        del,
+                        vec![],
+                        vec![loss(longitudinal_frequency, lowpass_c1, lowpass_c3)],
+                        None,
+                    )?);
+                }
+            }
+        }
+
+        // Stretching the string by the slope y' raises the tension by E A <y'^2> / 2 and the
+        // frequency by E A <y'^2> / 4 T. The travelling waves have the slopes v / c.
+        let tension_modulation = if params.tension_modulation {
+            let c = 2.0 * l * note_frequency;
+            e * std::f32::consts::PI * rcore * rcore / (2.0 * t * c * c)
+        } else {
+            0.0
+        };
+
+        Ok(Piano {
+            soundboard: Box::new(soundboard),
             nstrings,
             left_strings,
             right_strings,
+            periods,
+            horizontal_left_strings,
+            horizontal_right_strings,
+            horizontal_periods,
+            bridge_admittance: params.bridge_admittance,
+            bridge_force: [0.0, 0.0],
+            bridge: Junction::new(vec![string_impedance; nstrings]),
+            horizontal_bridge: if params.dual_polarisation {
+                Some(Junction::new(vec![string_impedance; nstrings]))
+            } else {
+                None
+            },
+            hammer_junctions: (0..nstrings)
+                .map(|_| Junction::new(vec![string_impedance, string_impedance]))
+                .collect(),
+            longitudinal_strings,
+            longitudinal_impedance: f32::sqrt(ea * rho_l),
+            longitudinal_coupling: params.longitudinal_coupling,
+            longitudinal_excitation: 0.5 * ea,
+            wave_speed: 2.0 * l * note_frequency,
+            pitch_bend: 1.0,
+            tension_modulation,
+            tension_update: 0,
+            tension_rise: vec![0.0; nstrings],
+            tension_attack: vec![v0 > 0.0; nstrings],
             hammers,
-        }
+            damper,
+            hammer_k: k,
+            hammer_p: p,
+            soft_hammer_k: soft_k,
+            soft_hammer_p: soft_p,
+            soft_pedal: false,
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn new_string(
         note_frequency: f32,
         sample_rate: f32,
@@ -109,12 +349,17 @@ impl Piano {
         lowpass_c1: f32,
         lowpass_c3: f32,
         thirian_b: f32,
-    ) -> (String, String) {
+        fractional_delay: FractionalDelayKind,
+        fractional_delay_order: usize,
+    ) -> Result<(String, String), Error> {
         let deltot = sample_rate / note_frequency;
-        let mut del1 = (hammer_position * 0.5 * deltot) as usize;
-        if del1 < 2 {
-            del1 = 1;
+        // The hammer sits between samples where the string to the agraffe has the two samples
+        // a centred interpolation needs, and on the nearest whole sample below that.
+        let mut hammer_delay = hammer_position * 0.5 * deltot;
+        if hammer_delay < 2.0 {
+            hammer_delay = hammer_delay.round().max(1.0);
         }
+        let del1 = hammer_delay.ceil() as usize;
 
         let mut left_filters = vec![];
         let mut right_filters = vec![];
@@ -130,8 +375,8 @@ impl Piano {
         right_filters.push(loss(note_frequency, lowpass_c1, lowpass_c3));
         let lowpass_delay = lowpass.groupdelay(note_frequency, sample_rate);
 
-        let mut del2 = (0.5 * (deltot - 2.0 * (del1 as f32)) - dispersion_delay) as usize;
-        let mut del3 = (0.5 * (deltot - 2.0 * (del1 as f32)) - lowpass_delay - 5.0) as usize;
+        let mut del2 = (0.5 * deltot - hammer_delay - dispersion_delay) as usize;
+        let mut del3 = (0.5 * deltot - hammer_delay - lowpass_delay - 5.0) as usize;
         if del2 < 2 {
             del2 = 1;
         }
@@ -140,74 +385,826 @@ impl Piano {
         }
 
         let fracdelay_delay = deltot
-            - (del1 as f32
-                + del1 as f32
-                + del2 as f32
-                + del3 as f32
-                + dispersion_delay
-                + lowpass_delay);
-        let fracdelay = thirian(fracdelay_delay, fracdelay_delay as usize);
-        right_filters.push(thirian(fracdelay_delay, fracdelay_delay as usize));
-        let tuning_delay = fracdelay.groupdelay(note_frequency, sample_rate);
-
-        println!("total delay = {}/{}, leftdel = {}/{}, rightdel = {}/{}, dispersion delay = {}, lowpass delay = {}, fractional delay = {}/{}",
-            del1 as f32+del1 as f32+del2 as f32+del3 as f32+dispersion_delay+lowpass_delay+tuning_delay,deltot, del1, del1, del2, del3, dispersion_delay, lowpass_delay, tuning_delay, fracdelay_delay
+            - (2.0 * hammer_delay + del2 as f32 + del3 as f32 + dispersion_delay + lowpass_delay);
+        // the shortest delays and the filters do not fit in a period of the string
+        if fracdelay_delay.is_nan() || fracdelay_delay < 0.0 {
+            return Err(Error::SampleRateTooLow {
+                note_frequency,
+                sample_rate,
+            });
+        }
+        let fracdelay: Box<dyn FractionalDelay> = match fractional_delay {
+            FractionalDelayKind::Thiran => Box::new(ThiranDelay::new(fracdelay_delay)),
+            FractionalDelayKind::Lagrange => {
+                Box::new(LagrangeDelay::new(fractional_delay_order, fracdelay_delay))
+            }
+            FractionalDelayKind::Farrow => {
+                Box::new(FarrowDelay::new(fractional_delay_order, fracdelay_delay))
+            }
+        };
+
+        let mut left_string = String::try_new(del1, del1, vec![], vec![], None)?;
+        left_string.set_rest_length(hammer_delay);
+        let mut right_string =
+            String::try_new(del2, del3, left_filters, right_filters, Some(fracdelay))?;
+        right_string.set_filter_delays(dispersion_delay, lowpass_delay + fracdelay_delay);
+
+        Ok((left_string, right_string))
+    }
+
+    pub fn strike(&mut self, v0: f32) {
+        // una corda shifts the action so that the hammer misses one of the unison strings
+        let nstruck = if self.soft_pedal && self.nstrings > 1 {
+            self.nstrings - 1
+        } else {
+            self.nstrings
+        };
+        let (k, p) = if self.soft_pedal {
+            (self.soft_hammer_k, self.soft_hammer_p)
+        } else {
+            (self.hammer_k, self.hammer_p)
+        };
+        for i in 0..self.nstrings {
+            self.hammers[i].set_felt(k, p);
+            if i < nstruck {
+                self.hammers[i].strike(v0);
+                self.tension_attack[i] = v0 > 0.0;
+            } else {
+                self.hammers[i].strike(0.0);
+            }
+        }
+        self.lift_damper();
+    }
+
+    // takes effect from the next strike
+    pub fn set_soft_pedal(&mut self, down: bool) {
+        self.soft_pedal = down;
+    }
+
+    pub fn engage_damper(&mut self) {
+        if let Some(damper) = self.damper.as_mut() {
+            damper.engage();
+        }
+    }
+
+    pub fn lift_damper(&mut self) {
+        if let Some(damper) = self.damper.as_mut() {
+            damper.lift();
+        }
+    }
+
+    pub fn set_soundboard(&mut self, soundboard: Box<dyn Soundboard>) {
+        self.soundboard = soundboard;
+    }
+
+    // Velocity of the strings at `position` along the speaking length, from 0 at the agraffe
+    // to 1 at the bridge, for a pickup or microphone on the strings.
+    pub fn pickup(&self, position: f32) -> f32 {
+        let mut v = 0.0;
+        for i in 0..self.nstrings {
+            let (to_left, to_right) =
+                Self::read_waves(&self.left_strings[i], &self.right_strings[i], position);
+            v += to_left + to_right;
+        }
+        v
+    }
+
+    // the travelling waves at `position` along the speaking length of the two halves of a string
+    fn read_waves(left: &String, right: &String, position: f32) -> (f32, f32) {
+        let left_length = left.span();
+        let right_length = right.span();
+        let x = position * (left_length + right_length);
+        if x < left_length {
+            left.read_waves(x / left_length)
+        } else {
+            right.read_waves((x - left_length) / right_length)
+        }
+    }
+
+    // Squared slope of a transverse string at `position`. A wave travelling to the right
+    // has the slope -v / c and one travelling to the left v / c.
+    fn slope_squared(left: &String, right: &String, position: f32, wave_speed: f32) -> f32 {
+        let (to_left, to_right) = Self::read_waves(left, right, position);
+        let slope = (to_left - to_right) / wave_speed;
+        slope * slope
+    }
+
+    // Drives the longitudinal wave of string `string_idx` by the force E A / 2 d(y'^2)/dx on each of
+    // its segments, and returns its force on the bridge. A fixed number of segments keeps the cost
+    // apart from the length of the string, and resolves the lower longitudinal modes.
+    fn go_longitudinal(&mut self, string_idx: usize) -> f32 {
+        let wave_speed = self.wave_speed;
+        let slope_squared = |piano: &Piano, position: f32| {
+            let mut q = Self::slope_squared(
+                &piano.left_strings[string_idx],
+                &piano.right_strings[string_idx],
+                position,
+                wave_speed,
+            );
+            if string_idx < piano.horizontal_left_strings.len() {
+                q += Self::slope_squared(
+                    &piano.horizontal_left_strings[string_idx],
+                    &piano.horizontal_right_strings[string_idx],
+                    position,
+                    wave_speed,
+                );
+            }
+            q
+        };
+
+        let string = &mut self.longitudinal_strings[string_idx];
+        string.do_delay();
+        let force = 2.0 * self.longitudinal_impedance * string.v_at_right_to_right;
+        string.v_at_right_to_left = -string.v_at_right_to_right;
+        string.v_at_left_to_right = -string.v_at_left_to_left;
+
+        let segments = LONGITUDINAL_SEGMENTS.min(string.length() as usize);
+        let mut last = slope_squared(self, 0.0);
+        for j in 0..segments {
+            let q = slope_squared(self, (j + 1) as f32 / segments as f32);
+            // a point force f raises the velocity by f / 2 Z in each direction
+            let segment_force = self.longitudinal_excitation * (q - last);
+            self.longitudinal_strings[string_idx].write(
+                (j as f32 + 0.5) / segments as f32,
+                segment_force / self.longitudinal_impedance,
+            );
+            last = q;
+        }
+        force
+    }
+
+    // Raises the pitch by `ratio` over the next PITCH_RAMP samples. Ratios below `min_pitch_bend`
+    // are held there.
+    pub fn set_pitch_bend(&mut self, ratio: f32) {
+        let ratio = ratio.max(self.min_pitch_bend());
+        self.pitch_bend = ratio;
+        for i in 0..self.nstrings {
+            self.set_pitch(i, ratio);
+        }
+    }
+
+    // The lowest ratio of `set_pitch_bend`, where the delay lines of a string reach MAX_LENGTH_SCALE
+    // times their size. The filters keep their delay, so this is a little above an octave down.
+    pub fn min_pitch_bend(&self) -> f32 {
+        let mut ratio: f32 = 0.0;
+        for i in 0..self.nstrings {
+            ratio = ratio.max(Self::min_string_pitch(
+                &self.left_strings[i],
+                &self.right_strings[i],
+                self.periods[i],
+            ));
+            if let Some(&period) = self.horizontal_periods.get(i) {
+                ratio = ratio.max(Self::min_string_pitch(
+                    &self.horizontal_left_strings[i],
+                    &self.horizontal_right_strings[i],
+                    period,
+                ));
+            }
+        }
+        ratio
+    }
+
+    fn min_string_pitch(left: &String, right: &String, period: f32) -> f32 {
+        let delay_lines = 2.0 * (left.length() + right.length());
+        let filters = period - delay_lines;
+        period / (MAX_LENGTH_SCALE as f32 * delay_lines + filters)
+    }
+
+    fn set_pitch(&mut self, string_idx: usize, ratio: f32) {
+        Self::scale_string(
+            &mut self.left_strings[string_idx],
+            &mut self.right_strings[string_idx],
+            self.periods[string_idx],
+            ratio,
         );
+        if let Some(period) = self.horizontal_periods.get(string_idx) {
+            Self::scale_string(
+                &mut self.horizontal_left_strings[string_idx],
+                &mut self.horizontal_right_strings[string_idx],
+                *period,
+                ratio,
+            );
+        }
+    }
 
-        let left_string = String::new(del1, del1, vec![], vec![]);
-        let right_string = String::new(del2, del3, left_filters, right_filters);
+    // scales the delay lines of a string, leaving the delay of the filters, to divide the period by `ratio`
+    fn scale_string(left: &mut String, right: &mut String, period: f32, ratio: f32) {
+        let scale = if ratio == 1.0 {
+            1.0
+        } else {
+            let delay_lines = 2.0 * (left.length() + right.length());
+            let filters = period - delay_lines;
+            (period / ratio - filters) / delay_lines
+        };
+        left.ramp_length_scale(scale, PITCH_RAMP);
+        right.ramp_length_scale(scale, PITCH_RAMP);
+    }
 
-        (left_string, right_string)
+    // mean square velocity along both halves of a string
+    fn mean_square(left: &String, right: &String) -> f32 {
+        (left.mean_square() * left.length() + right.mean_square() * right.length())
+            / (left.length() + right.length())
     }
 
     fn do_delay(&mut self, string_idx: usize) {
         self.left_strings[string_idx].do_delay();
         self.right_strings[string_idx].do_delay();
+        if string_idx < self.horizontal_left_strings.len() {
+            self.horizontal_left_strings[string_idx].do_delay();
+            self.horizontal_right_strings[string_idx].do_delay();
+        }
     }
 
     pub fn go(&mut self) -> f32 {
+        let (dual_force_of_input_at_string_soundboard, string_impedance_at_soundboard) =
+            self.go_to_soundboard();
+        let velocity_at_string_soundboard = self.soundboard.go(
+            dual_force_of_input_at_string_soundboard,
+            string_impedance_at_soundboard,
+        );
+        self.go_from_soundboard(velocity_at_string_soundboard);
+
+        self.soundboard.output()
+    }
+
+    // Renders `out.len()` samples. Nothing is allocated, so this can run in an audio callback.
+    pub fn process(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            *x = self.go();
+        }
+    }
+
+    // dual_force_of_input of the horizontal polarisation at the bridge, 0 without it
+    pub fn horizontal_dual_force(&self) -> f32 {
+        match self.horizontal_bridge.as_ref() {
+            Some(bridge) => bridge.dual_force_of_input(),
+            None => 0.0,
+        }
+    }
+
+    // First half of `go`. Updates the strings up to the soundboard and returns
+    // the dual_force_of_input at the soundboard and the sum of string impedance there,
+    // so that several notes can share one soundboard junction.
+    pub fn go_to_soundboard(&mut self) -> (f32, f32) {
         // delay line update
         for i in 0..self.nstrings {
             self.do_delay(i);
         }
 
-        let mut dual_force_of_input_at_string_soundboard: f32 = 0.0;
-        let mut dual_force_of_input_at_string_hammer: Vec<f32> = vec![];
-        // calculate dual_force_of_input
-        for i in 0..self.nstrings {
-            let vin =
-                self.right_strings[i].v_at_left_to_left + self.left_strings[i].v_at_right_to_right;
-            let hammer_force = self.hammers[i].calculate_force(
-                2.0 * self.string_impedance * vin,
-                2.0 * self.string_impedance,
-            );
-            dual_force_of_input_at_string_hammer
-                .push(2.0 * self.string_impedance * vin + hammer_force);
+        // the pitch follows the tension every PITCH_RAMP samples, and ramps in between
+        if self.tension_modulation > 0.0 && self.tension_update == 0 {
+            self.tension_update = PITCH_RAMP;
+            for i in 0..self.nstrings {
+                // both polarisations stretch the string
+                let mut mean_square =
+                    Self::mean_square(&self.left_strings[i], &self.right_strings[i]);
+                if i < self.horizontal_left_strings.len() {
+                    mean_square += Self::mean_square(
+                        &self.horizontal_left_strings[i],
+                        &self.horizontal_right_strings[i],
+                    );
+                }
+                // Once the string stretches less than at its peak, the pitch only falls back
+                // towards rest, so that changing the length of the lines cannot pump energy
+                // into the string.
+                let rise = (self.tension_modulation * mean_square).min(MAX_TENSION_RISE);
+                if self.tension_attack[i] && rise >= self.tension_rise[i] {
+                    self.tension_rise[i] = rise;
+                } else {
+                    self.tension_attack[i] = false;
+                    self.tension_rise[i] = self.tension_rise[i].min(rise);
+                }
+                self.set_pitch(i, self.pitch_bend * (1.0 + self.tension_rise[i]));
+            }
+        }
+        self.tension_update = self.tension_update.saturating_sub(1);
+
+        // damper felt
+        if let Some(damper) = self.damper.as_mut() {
+            let gain = damper.next_gain();
+            for i in 0..self.nstrings {
+                self.right_strings[i].v_at_right_to_right *= gain;
+            }
+            for string in self.horizontal_right_strings.iter_mut() {
+                string.v_at_right_to_right *= gain;
+            }
         }
+
+        // The horizontal polarisation meets the bridge through the admittance matrix alone,
+        // and the vertical one through the soundboard. Each polarisation is solved implicitly
+        // with its own admittance, and the force of the other one acts with one sample of delay,
+        // which avoids a delay-free loop through the soundboard.
+        if let Some(bridge) = self.horizontal_bridge.as_mut() {
+            for (i, string) in self.horizontal_right_strings.iter().enumerate() {
+                bridge.set_incoming(i, string.v_at_right_to_right);
+            }
+            let y = self.bridge_admittance;
+            let velocity_at_string_bridge = (y[1][1] * bridge.dual_force_of_input()
+                + y[1][0] * self.bridge_force[0])
+                / (1.0 + y[1][1] * bridge.impedance());
+            bridge.set_velocity(velocity_at_string_bridge);
+            self.bridge_force[1] = bridge.force();
+
+            // no hammer acts on the horizontal polarisation
+            for i in 0..self.nstrings {
+                self.horizontal_left_strings[i].v_at_right_to_left =
+                    self.horizontal_right_strings[i].v_at_left_to_left;
+                self.horizontal_right_strings[i].v_at_left_to_right =
+                    self.horizontal_left_strings[i].v_at_right_to_right;
+            }
+        }
+
         for i in 0..self.nstrings {
+            self.bridge
+                .set_incoming(i, self.right_strings[i].v_at_right_to_right);
+        }
+        let mut dual_force_of_input_at_string_soundboard = self.bridge.dual_force_of_input();
+        // the longitudinal waves push on the rigid bridge, which passes part of their force to the soundboard
+        for i in 0..self.longitudinal_strings.len() {
             dual_force_of_input_at_string_soundboard +=
-                2.0 * self.string_impedance * self.right_strings[i].v_at_right_to_right;
+                self.longitudinal_coupling * self.go_longitudinal(i);
         }
 
-        // calculate velocity
+        // Each string has its own junction at the hammer,
+        // so its dual_force_of_input and velocity are calculated together.
         for i in 0..self.nstrings {
-            let velocity_at_string_hammer =
-                dual_force_of_input_at_string_hammer[i] / (2.0 * self.string_impedance);
-            self.left_strings[i].v_at_right_to_left =
-                velocity_at_string_hammer - self.left_strings[i].v_at_right_to_right;
-            self.right_strings[i].v_at_left_to_right =
-                velocity_at_string_hammer - self.right_strings[i].v_at_left_to_left;
-        }
-        let velocity_at_string_soundboard = dual_force_of_input_at_string_soundboard
-            / (self.nstrings as f32 * self.string_impedance + self.soundboard_impedance);
+            let junction = &mut self.hammer_junctions[i];
+            junction.set_incoming(0, self.left_strings[i].v_at_right_to_right);
+            junction.set_incoming(1, self.right_strings[i].v_at_left_to_left);
+            let hammer_force = self.hammers[i]
+                .calculate_force(junction.dual_force_of_input(), junction.impedance());
+            junction.scatter(hammer_force);
+            self.left_strings[i].v_at_right_to_left = junction.outgoing(0);
+            self.right_strings[i].v_at_left_to_right = junction.outgoing(1);
+        }
+
+        (
+            dual_force_of_input_at_string_soundboard,
+            self.bridge.impedance(),
+        )
+    }
+
+    // Second half of `go`. Reflects the waves at both ends of the strings
+    // given the velocity at the soundboard junction.
+    pub fn go_from_soundboard(&mut self, velocity_at_string_soundboard: f32) {
+        let mut velocity_at_string_bridge = velocity_at_string_soundboard;
+        if let Some(bridge) = self.horizontal_bridge.as_ref() {
+            // v = v_soundboard + y00 (dual_force_of_input - Z v) + y01 F_horizontal
+            let y = self.bridge_admittance;
+            velocity_at_string_bridge = (velocity_at_string_soundboard
+                + y[0][0] * self.bridge.dual_force_of_input()
+                + y[0][1] * self.bridge_force[1])
+                / (1.0 + y[0][0] * self.bridge.impedance());
+            for i in 0..self.nstrings {
+                self.horizontal_left_strings[i].v_at_left_to_right =
+                    -self.horizontal_left_strings[i].v_at_left_to_left;
+                self.horizontal_right_strings[i].v_at_right_to_left = bridge.outgoing(i);
+            }
+        }
+        self.bridge.set_velocity(velocity_at_string_bridge);
+        self.bridge_force[0] = self.bridge.force();
         for i in 0..self.nstrings {
             self.left_strings[i].v_at_left_to_right = -self.left_strings[i].v_at_left_to_left;
-            self.right_strings[i].v_at_right_to_left =
-                velocity_at_string_soundboard - self.right_strings[i].v_at_right_to_right;
+            self.right_strings[i].v_at_right_to_left = self.bridge.outgoing(i);
+        }
+    }
+}
+
+#[test]
+fn piano_soft_pedal_work() {
+    let mut normal = Piano::new(60, 44100.0, 0.0);
+    let mut soft = Piano::new(60, 44100.0, 0.0);
+    soft.set_soft_pedal(true);
+    normal.strike(5.0);
+    soft.strike(5.0);
+
+    let mut normal_peak: f32 = 0.0;
+    let mut soft_peak: f32 = 0.0;
+    for _ in 0..4410 {
+        normal_peak = normal_peak.max(normal.go().abs());
+        soft_peak = soft_peak.max(soft.go().abs());
+    }
+    assert!(soft_peak > 0.0);
+    assert!(soft_peak < normal_peak);
+}
+
+#[test]
+fn piano_process_work() {
+    let mut sample_by_sample = Piano::new(48, 44100.0, 5.0);
+    let mut block = Piano::new(48, 44100.0, 5.0);
+    let mut out = [0.0; 256];
+    for _ in 0..8 {
+        block.process(&mut out);
+        for &x in out.iter() {
+            assert_eq!(sample_by_sample.go(), x);
+        }
+    }
+}
+
+#[test]
+fn piano_tuning_work() {
+    // the loop delay of the strings at the fundamental is one period across the keyboard
+    let sample_rate = 44100.0;
+    let kinds = [
+        FractionalDelayKind::Thiran,
+        FractionalDelayKind::Lagrange,
+        FractionalDelayKind::Farrow,
+    ];
+    for (&note, &kind) in [21, 40, 60, 80, 100]
+        .iter()
+        .flat_map(|note| kinds.iter().map(move |kind| (note, kind)))
+    {
+        let note_frequency = 440.0 * f32::powf(2.0, (note as f32 - 69.0) / 12.0);
+        let (left, right) = Piano::new_string(
+            note_frequency,
+            sample_rate,
+            1.0 / 7.0,
+            0.25,
+            5.85,
+            1.0e-4,
+            kind,
+            3,
+        )
+        .unwrap();
+        let frequencies = [note_frequency];
+        let loop_response = left
+            .to_left_response(&frequencies, sample_rate)
+            .chain(&left.to_right_response(&frequencies, sample_rate))
+            .chain(&right.to_left_response(&frequencies, sample_rate))
+            .chain(&right.to_right_response(&frequencies, sample_rate));
+        assert!((loop_response.group_delay[0] - sample_rate / note_frequency).abs() < 1.0e-2);
+    }
+}
+
+#[test]
+fn piano_hammer_position_work() {
+    // the hammer moves between samples, and the loop keeps its period
+    let sample_rate = 44100.0;
+    let note_frequency = 261.63;
+    let mut outputs = vec![];
+    for &hammer_position in [0.12, 0.125].iter() {
+        let (left, right) = Piano::new_string(
+            note_frequency,
+            sample_rate,
+            hammer_position,
+            0.25,
+            5.85,
+            1.0e-4,
+            FractionalDelayKind::Thiran,
+            3,
+        )
+        .unwrap();
+        let hammer_delay = hammer_position * 0.5 * sample_rate / note_frequency;
+        assert!((left.length() - hammer_delay).abs() < 1.0e-4);
+        let frequencies = [note_frequency];
+        let loop_response = left
+            .to_left_response(&frequencies, sample_rate)
+            .chain(&left.to_right_response(&frequencies, sample_rate))
+            .chain(&right.to_left_response(&frequencies, sample_rate))
+            .chain(&right.to_right_response(&frequencies, sample_rate));
+        assert!((loop_response.group_delay[0] - sample_rate / note_frequency).abs() < 1.0e-2);
+
+        let params = PianoParams {
+            hammer_position,
+            ..PianoParams::default()
+        };
+        let mut piano = Piano::with_params(60, sample_rate, 5.0, &params);
+        outputs.push((0..4410).map(|_| piano.go()).collect::<Vec<f32>>());
+    }
+    // both positions fell on the same sample before
+    assert!(outputs[0] != outputs[1]);
+    assert!(outputs.iter().flatten().all(|v| v.is_finite()));
+}
+
+#[test]
+fn piano_pickup_work() {
+    let mut piano = Piano::new(60, 44100.0, 0.0);
+    piano.strike(5.0);
+    // the wave from the hammer at 1/7 reaches 0.3 after about a tenth of a period
+    let mut first = None;
+    for n in 0..441 {
+        piano.go();
+        if first.is_none() && piano.pickup(0.3).abs() > 1.0e-6 {
+            first = Some(n);
         }
+    }
+    let first = first.unwrap();
+    assert!(first > 5 && first < 30, "{}", first);
+}
 
-        let output = velocity_at_string_soundboard;
+#[test]
+fn piano_pitch_work() {
+    // the loop delay of the first string at the fundamental
+    let period = |piano: &Piano, frequency: f32| {
+        let frequencies = [frequency];
+        let (left, right) = (&piano.left_strings[0], &piano.right_strings[0]);
+        left.to_left_response(&frequencies, 44100.0)
+            .chain(&left.to_right_response(&frequencies, 44100.0))
+            .chain(&right.to_left_response(&frequencies, 44100.0))
+            .chain(&right.to_right_response(&frequencies, 44100.0))
+            .group_delay[0]
+    };
 
-        output
+    let mut piano = Piano::new(45, 44100.0, 5.0);
+    let ratio = f32::powf(2.0, 2.0 / 12.0);
+    piano.set_pitch_bend(ratio);
+    assert!((period(&piano, 110.0 * ratio) - 44100.0 / (110.0 * ratio)).abs() < 1.0e-1);
+    piano.set_pitch_bend(1.0);
+    assert!((period(&piano, 110.0) - 44100.0 / 110.0).abs() < 1.0e-2);
+
+    // a hard strike sounds sharper than a soft one while it rings loudly
+    let params = PianoParams {
+        tension_modulation: true,
+        ..PianoParams::default()
+    };
+    let mut soft = Piano::with_params(45, 44100.0, 0.5, &params);
+    let mut hard = Piano::with_params(45, 44100.0, 5.0, &params);
+    for _ in 0..2205 {
+        soft.go();
+        hard.go();
+    }
+    let cents = 1200.0 * f32::log2(period(&soft, 110.0) / period(&hard, 110.0));
+    assert!(cents > 0.1 && cents < 20.0, "{}", cents);
+
+    // a bend below the reach of the delay lines is held there
+    piano.set_pitch_bend(0.1);
+    assert_eq!(piano.min_pitch_bend(), piano.pitch_bend);
+    assert!(piano.pitch_bend > 0.5 && piano.pitch_bend < 0.55);
+}
+
+#[test]
+fn piano_pitch_sweep_work() {
+    // the step from one sample to the next of the wave at the bridge, for a bend swept in
+    // semitones as from a pitch wheel, stays close to that of the note held still
+    let largest_step = |bend: bool| {
+        let mut piano = Piano::new(45, 44100.0, 5.0);
+        let mut last = 0.0;
+        let mut largest: f32 = 0.0;
+        for n in 0..8820 {
+            if bend && n % 441 == 0 {
+                let semitones = if n < 4410 { n } else { 8820 - n } as f32 / 441.0;
+                piano.set_pitch_bend(f32::powf(2.0, semitones / 12.0));
+            }
+            piano.go();
+            let y = piano.right_strings[0].v_at_right_to_right;
+            if n > 0 {
+                largest = largest.max((y - last).abs());
+            }
+            last = y;
+        }
+        largest
+    };
+    let still = largest_step(false);
+    let swept = largest_step(true);
+    assert!(swept < 1.5 * still, "{} {}", swept, still);
+}
+
+#[test]
+fn piano_dual_polarisation_work() {
+    let params = PianoParams {
+        dual_polarisation: true,
+        ..PianoParams::default()
+    };
+    let mut single = Piano::new(45, 44100.0, 5.0);
+    let mut dual = Piano::with_params(45, 44100.0, 5.0, &params);
+    let mut differs = false;
+    for _ in 0..44100 {
+        let x = single.go();
+        let y = dual.go();
+        assert!(y.is_finite());
+        differs |= x != y;
+    }
+    assert!(differs);
+
+    // the bridge passes the vertical motion on to the horizontal polarisation
+    let horizontal: f32 = (0..dual.nstrings)
+        .map(|i| {
+            Piano::mean_square(
+                &dual.horizontal_left_strings[i],
+                &dual.horizontal_right_strings[i],
+            )
+        })
+        .sum();
+    assert!(horizontal > 0.0);
+}
+
+#[test]
+fn piano_two_stage_decay_work() {
+    // A single string decays at a steady rate. With a horizontal polarisation that loses little
+    // at the bridge, the sound falls fast while the vertical one leads and slowly after.
+    let bridge_admittance = [[2.5e-4, 1.4e-4], [1.4e-4, 4.0e-5]];
+    let decay_rates = |dual_polarisation: bool| {
+        let params = PianoParams {
+            two_strings_from: 200,
+            three_strings_from: 200,
+            dual_polarisation,
+            horizontal_loss: 0.2,
+            bridge_admittance,
+            ..PianoParams::default()
+        };
+        let mut piano = Piano::with_params(45, 44100.0, 5.0, &params);
+        // level in dB of each half second
+        let levels: Vec<f32> = (0..12)
+            .map(|_| {
+                let energy: f32 = (0..22050).map(|_| piano.go().powi(2)).sum();
+                10.0 * energy.log10()
+            })
+            .collect();
+        (levels[1] - levels[3], levels[9] - levels[11])
+    };
+    let (early, late) = decay_rates(false);
+    assert!(late > 0.8 * early, "{} {}", early, late);
+    let (early, late) = decay_rates(true);
+    assert!(late < 0.7 * early, "{} {}", early, late);
+
+    // a bridge that gives energy back to the strings
+    let params = PianoParams {
+        dual_polarisation: true,
+        bridge_admittance: [[0.0, 2.0e-4], [2.0e-4, 1.0e-5]],
+        ..PianoParams::default()
+    };
+    assert_eq!(
+        Some(Error::InvalidParameter("bridge admittance")),
+        Piano::try_with_params(60, 44100.0, 5.0, &params).err()
+    );
+
+    // a passive bridge never raises the level again, over a long ring
+    let params = PianoParams {
+        dual_polarisation: true,
+        bridge_admittance,
+        ..PianoParams::default()
+    };
+    for &note in [60, 88, 108].iter() {
+        let mut piano = Piano::with_params(note, 44100.0, 5.0, &params);
+        // level in dB of each second
+        let levels: Vec<f32> = (0..20)
+            .map(|_| {
+                let energy: f32 = (0..44100).map(|_| piano.go().powi(2)).sum();
+                10.0 * energy.log10()
+            })
+            .collect();
+        assert!(levels.iter().all(|level| !level.is_nan()));
+        assert!(
+            levels[1..].iter().all(|&level| level < levels[0]),
+            "{}",
+            note
+        );
+        assert!(levels[19] < levels[0] - 20.0, "{} {:?}", note, levels);
+    }
+}
+
+#[test]
+fn piano_longitudinal_work() {
+    use num_complex::Complex;
+
+    let params = PianoParams {
+        longitudinal: true,
+        ..PianoParams::default()
+    };
+    // the part of the sound from the longitudinal waves, relative to the rest
+    let relative = |v0: f32| {
+        let mut transverse = Piano::new(21, 44100.0, v0);
+        let mut longitudinal = Piano::with_params(21, 44100.0, v0, &params);
+        assert!(!longitudinal.longitudinal_strings.is_empty());
+        let mut difference = 0.0;
+        let mut energy = 0.0;
+        for _ in 0..4410 {
+            let x = transverse.go();
+            let y = longitudinal.go();
+            assert!(y.is_finite());
+            difference += (y - x) * (y - x);
+            energy += x * x;
+        }
+        difference / energy
+    };
+    // driven by the square of the transverse motion, it grows faster than the transverse waves
+    let soft = relative(1.0);
+    let hard = relative(4.0);
+    assert!(soft > 0.0);
+    assert!(hard > 4.0 * soft, "{} {}", soft, hard);
+
+    // the treble has its longitudinal modes above the Nyquist frequency
+    assert!(Piano::with_params(100, 44100.0, 1.0, &params)
+        .longitudinal_strings
+        .is_empty());
+
+    // the phantom partials gather at the longitudinal frequency, the loop of the longitudinal string
+    let mut transverse = Piano::new(21, 44100.0, 4.0);
+    let mut longitudinal = Piano::with_params(21, 44100.0, 4.0, &params);
+    let string = &longitudinal.longitudinal_strings[0];
+    let frequencies = [50.0];
+    let period = string
+        .to_left_response(&frequencies, 44100.0)
+        .chain(&string.to_right_response(&frequencies, 44100.0))
+        .group_delay[0];
+    let longitudinal_frequency = 44100.0 / period;
+    let difference: Vec<f32> = (0..44100)
+        .map(|_| longitudinal.go() - transverse.go())
+        .collect();
+    // the largest magnitude of the spectrum within 10 % of `frequency`
+    let peak = |frequency: f32| {
+        (0..41)
+            .map(|i| {
+                let omega =
+                    2.0 * std::f32::consts::PI * frequency * (0.9 + 0.005 * i as f32) / 44100.0;
+                difference
+                    .iter()
+                    .enumerate()
+                    .fold(Complex::new(0.0, 0.0), |h, (n, &x)| {
+                        h + Complex::from_polar(x, -omega * n as f32)
+                    })
+                    .norm()
+            })
+            .fold(0.0, f32::max)
+    };
+    let at = peak(longitudinal_frequency);
+    assert!(at > 3.0 * peak(0.7 * longitudinal_frequency));
+    assert!(at > 3.0 * peak(1.4 * longitudinal_frequency));
+}
+
+#[test]
+fn piano_try_new_work() {
+    assert!(Piano::try_new(60, 44100.0, 5.0).is_ok());
+    assert_eq!(
+        Some(Error::InvalidNote(12)),
+        Piano::try_new(12, 44100.0, 5.0).err()
+    );
+    // the infallible constructors still take notes beyond the keyboard
+    Piano::new(110, 44100.0, 5.0);
+    assert_eq!(
+        Some(Error::NotANumber("hammer velocity")),
+        Piano::try_new(60, 44100.0, f32::NAN).err()
+    );
+    assert!(matches!(
+        Piano::try_new(108, 8000.0, 5.0),
+        Err(Error::SampleRateTooLow { .. })
+    ));
+
+    let params = PianoParams {
+        rho: f32::NAN,
+        ..PianoParams::default()
+    };
+    assert_eq!(
+        Some(Error::NotANumber("rho")),
+        Piano::try_with_params(60, 44100.0, 5.0, &params).err()
+    );
+    let params = PianoParams {
+        hammer_mass_bass: 0.0,
+        ..PianoParams::default()
+    };
+    assert_eq!(
+        Some(Error::InvalidParameter("hammer mass")),
+        Piano::try_with_params(21, 44100.0, 5.0, &params).err()
+    );
+}
+
+#[test]
+fn piano_hard_strike_work() {
+    // the stiff treble felt at the hammer velocity of MIDI velocity 127
+    for note in [93, 102, 105, 108] {
+        let mut piano = Piano::new(note, 44100.0, 6.0);
+        let mut peak: f32 = 0.0;
+        for _ in 0..22050 {
+            let v = piano.go();
+            assert!(v.is_finite());
+            peak = peak.max(v.abs());
+        }
+        assert!(peak > 0.0 && peak < 1.0);
+    }
+}
+
+#[test]
+fn piano_tension_modulation_decay_work() {
+    // every key decays over ten seconds, and its pitch only falls back after the strike
+    let params = PianoParams {
+        tension_modulation: true,
+        ..PianoParams::default()
+    };
+    for note in LOWEST_NOTE..(HIGHEST_NOTE + 1) {
+        let mut piano = Piano::with_params(note, 44100.0, 4.0, &params);
+        let mut rise = 0.0;
+        let mut peak_rise: f32 = 0.0;
+        // level in dB of each second
+        let levels: Vec<f32> = (0..10)
+            .map(|second| {
+                let mut energy = 0.0;
+                for _ in 0..44100 {
+                    energy += piano.go().powi(2);
+                    if second > 0 {
+                        assert!(piano.tension_rise[0] <= rise, "{}", note);
+                    }
+                    rise = piano.tension_rise[0];
+                    peak_rise = peak_rise.max(rise);
+                }
+                10.0 * energy.log10()
+            })
+            .collect();
+        assert!(peak_rise > 0.0, "{}", note);
+        assert!(
+            levels[1..].iter().all(|&level| level < levels[0]),
+            "{}",
+            note
+        );
+        assert!(levels[9] < levels[0] - 20.0, "{} {:?}", note, levels);
     }
 }
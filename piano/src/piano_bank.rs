@@ -0,0 +1,398 @@
+use super::error::Error;
+use super::junction::Junction;
+use super::params::PianoParams;
+use super::piano::Piano;
+use super::soundboard::{ModalSoundboard, Soundboard};
+
+/*
+PianoBank owns one Piano voice per key of an 88-key keyboard (A0 = 21 to C8 = 108).
+
+Events take effect on the next call of `go`, so calling `note_on` / `note_off`
+between two `go` calls places them at that exact sample.
+
+All strings meet at one soundboard junction, so every undamped string resonates
+sympathetically with the others. A voice is computed while it is sounding or while
+its dampers are lifted, either by its key or by the sustain pedal.
+
+`go_multi` renders several channels. Each channel listens to its own pickup on the
+soundboard, and the sound of the strings at the bridge is panned by keyboard position
+from bass on the first channel to treble on the last.
+*/
+
+pub use super::params::{HIGHEST_NOTE, LOWEST_NOTE, NUM_KEYS};
+
+// dual_force_of_input of a voice at the soundboard, about 1e-7 m/s on the bridge
+const SILENCE_THRESHOLD: f32 = 4.0e-4;
+const SILENCE_DURATION: f32 = 0.1;
+
+// scales dual_force_of_input of a voice to a velocity on the bridge
+const DIRECT_IMPEDANCE: f32 = 4000.0;
+const DIRECT_MIX: f32 = 0.5;
+
+struct Voice {
+    piano: Piano,
+    key_down: bool,
+    sounding: bool,
+    silent_samples: usize,
+    coupled: bool,
+    // keyboard position from 0 at the lowest key to 1 at the highest
+    position: f32,
+}
+
+pub struct PianoBank {
+    voices: Vec<Voice>,
+    soundboard: Box<dyn Soundboard>,
+    // a port per voice, of zero impedance while the voice is not coupled
+    soundboard_junction: Junction,
+    sustain: bool,
+    soft: bool,
+    silence_samples: usize,
+    nchannels: usize,
+    // to make a voice again at rest
+    sample_rate: f32,
+    params: PianoParams,
+}
+
+impl PianoBank {
+    pub fn new(sample_rate: f32) -> PianoBank {
+        Self::with_params(sample_rate, &PianoParams::default())
+    }
+
+    pub fn with_params(sample_rate: f32, params: &PianoParams) -> PianoBank {
+        Self::try_with_params(sample_rate, params).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // fails where a voice of the keyboard cannot be made with `params`
+    pub fn try_with_params(sample_rate: f32, params: &PianoParams) -> Result<PianoBank, Error> {
+        let mut voices = vec![];
+        for note in LOWEST_NOTE..(HIGHEST_NOTE + 1) {
+            voices.push(Voice {
+                piano: Piano::try_with_params(note, sample_rate, 0.0, params)?,
+                key_down: false,
+                sounding: false,
+                silent_samples: 0,
+                coupled: false,
+                position: (note - LOWEST_NOTE) as f32 / (NUM_KEYS - 1) as f32,
+            });
+        }
+        for voice in voices.iter_mut() {
+            voice.piano.engage_damper();
+        }
+        Ok(PianoBank {
+            soundboard: Box::new(ModalSoundboard::piano(sample_rate)),
+            soundboard_junction: Junction::new(vec![0.0; voices.len()]),
+            voices,
+            sustain: false,
+            soft: false,
+            nchannels: 0,
+            silence_samples: (SILENCE_DURATION * sample_rate) as usize,
+            sample_rate,
+            params: params.clone(),
+        })
+    }
+
+    pub fn set_soundboard(&mut self, soundboard: Box<dyn Soundboard>) {
+        self.soundboard = soundboard;
+        // the new soundboard has its pickups set on the next `go_multi`
+        self.nchannels = 0;
+    }
+
+    fn voice_index(note: usize) -> Option<usize> {
+        if (LOWEST_NOTE..(HIGHEST_NOTE + 1)).contains(&note) {
+            Some(note - LOWEST_NOTE)
+        } else {
+            None
+        }
+    }
+
+    // `velocity` is the hammer velocity in m/s, as `v0` of `Piano::new`.
+    // Notes outside the keyboard are ignored.
+    pub fn note_on(&mut self, note: usize, velocity: f32) {
+        if let Some(i) = Self::voice_index(note) {
+            let voice = &mut self.voices[i];
+            voice.piano.strike(velocity);
+            voice.key_down = true;
+            voice.sounding = true;
+            voice.silent_samples = 0;
+        }
+    }
+
+    pub fn note_off(&mut self, note: usize) {
+        if let Some(i) = Self::voice_index(note) {
+            let voice = &mut self.voices[i];
+            if !self.sustain {
+                voice.piano.engage_damper();
+            }
+            voice.key_down = false;
+        }
+    }
+
+    pub fn set_sustain_pedal(&mut self, down: bool) {
+        self.sustain = down;
+        for voice in self.voices.iter_mut() {
+            if down {
+                voice.piano.lift_damper();
+            } else if !voice.key_down {
+                voice.piano.engage_damper();
+            }
+        }
+    }
+
+    pub fn is_sustain_pedal_down(&self) -> bool {
+        self.sustain
+    }
+
+    // una corda; notes struck while the pedal is down hit fewer strings with softer felt
+    pub fn set_soft_pedal(&mut self, down: bool) {
+        self.soft = down;
+        for voice in self.voices.iter_mut() {
+            voice.piano.set_soft_pedal(down);
+        }
+    }
+
+    pub fn is_soft_pedal_down(&self) -> bool {
+        self.soft
+    }
+
+    pub fn is_key_down(&self, note: usize) -> bool {
+        match Self::voice_index(note) {
+            Some(i) => self.voices[i].key_down,
+            None => false,
+        }
+    }
+
+    pub fn is_sounding(&self, note: usize) -> bool {
+        match Self::voice_index(note) {
+            Some(i) => self.voices[i].sounding,
+            None => false,
+        }
+    }
+
+    pub fn num_sounding(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.sounding).count()
+    }
+
+    pub fn go(&mut self) -> f32 {
+        self.update(&mut []);
+        self.soundboard.output()
+    }
+
+    // Renders `out.len()` samples of the mono output without allocating.
+    // Events for the block have to be given before the call.
+    pub fn process(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            *x = self.go();
+        }
+    }
+
+    // Renders one frame into `out`, one sample per channel.
+    pub fn go_multi(&mut self, out: &mut [f32]) {
+        let nchannels = out.len();
+        if nchannels != self.nchannels {
+            let positions: Vec<f32> = (0..nchannels)
+                .map(|c| {
+                    if nchannels > 1 {
+                        0.2 + 0.6 * c as f32 / (nchannels - 1) as f32
+                    } else {
+                        0.5
+                    }
+                })
+                .collect();
+            self.soundboard.set_pickups(&positions);
+            self.nchannels = nchannels;
+        }
+
+        for x in out.iter_mut() {
+            *x = 0.0;
+        }
+        self.update(out);
+        for (c, x) in out.iter_mut().enumerate() {
+            *x = DIRECT_MIX * *x + (1.0 - DIRECT_MIX) * self.soundboard.output_at(c);
+        }
+    }
+
+    // equal power panning between neighbouring channels
+    fn pan(position: f32, channel: usize, nchannels: usize) -> f32 {
+        if nchannels < 2 {
+            return 1.0;
+        }
+        let distance = (position * (nchannels - 1) as f32 - channel as f32).abs();
+        if distance < 1.0 {
+            f32::sqrt(1.0 - distance)
+        } else {
+            0.0
+        }
+    }
+
+    // Steps every coupled voice and the soundboard by one sample,
+    // adding the panned sound of the strings at the bridge to `direct`.
+    fn update(&mut self, direct: &mut [f32]) {
+        let nchannels = direct.len();
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            voice.coupled = voice.sounding || voice.key_down || self.sustain;
+            if !voice.coupled {
+                self.soundboard_junction.set_impedance(i, 0.0);
+                continue;
+            }
+            let (dual_force, impedance) = voice.piano.go_to_soundboard();
+            // a voice that has blown up is made again at rest and kept off the junction,
+            // which would carry it to every other voice
+            if !dual_force.is_finite() || !impedance.is_finite() {
+                let note = LOWEST_NOTE + i;
+                voice.piano = Piano::with_params(note, self.sample_rate, 0.0, &self.params);
+                voice.piano.set_soft_pedal(self.soft);
+                if !self.sustain {
+                    voice.piano.engage_damper();
+                }
+                voice.sounding = false;
+                voice.silent_samples = 0;
+                voice.coupled = false;
+                self.soundboard_junction.set_impedance(i, 0.0);
+                continue;
+            }
+            // the incoming wave of the voice, as dual_force_of_input = 2 Z v^-
+            self.soundboard_junction.set_impedance(i, impedance);
+            self.soundboard_junction
+                .set_incoming(i, dual_force / (2.0 * impedance));
+            for (c, x) in direct.iter_mut().enumerate() {
+                *x += Self::pan(voice.position, c, nchannels) * dual_force / DIRECT_IMPEDANCE;
+            }
+
+            // a voice whose own contribution stays below the threshold has decayed away,
+            // also in the horizontal polarisation which reaches the soundboard through the bridge
+            let level = dual_force
+                .abs()
+                .max(voice.piano.horizontal_dual_force().abs());
+            if level < SILENCE_THRESHOLD {
+                voice.silent_samples += 1;
+                if voice.silent_samples > self.silence_samples {
+                    voice.sounding = false;
+                }
+            } else {
+                voice.silent_samples = 0;
+                voice.sounding = true;
+            }
+        }
+
+        let velocity_at_string_soundboard = self.soundboard.go(
+            self.soundboard_junction.dual_force_of_input(),
+            self.soundboard_junction.impedance(),
+        );
+        self.soundboard_junction
+            .set_velocity(velocity_at_string_soundboard);
+        for voice in self.voices.iter_mut() {
+            if voice.coupled {
+                voice
+                    .piano
+                    .go_from_soundboard(velocity_at_string_soundboard);
+            }
+        }
+    }
+}
+
+#[test]
+fn piano_bank_work() {
+    let mut bank = PianoBank::new(44100.0);
+    assert_eq!(0, bank.num_sounding());
+    assert_eq!(0.0, bank.go());
+
+    bank.note_on(60, 5.0);
+    bank.note_on(64, 5.0);
+    bank.note_on(200, 5.0);
+    assert_eq!(2, bank.num_sounding());
+    assert!(bank.is_key_down(60));
+
+    let mut peak: f32 = 0.0;
+    for _ in 0..4410 {
+        let v = bank.go();
+        assert!(v.is_finite());
+        peak = peak.max(v.abs());
+    }
+    assert!(peak > 0.0);
+
+    bank.note_off(60);
+    assert!(!bank.is_key_down(60));
+}
+
+#[test]
+fn piano_bank_damper_work() {
+    let mut bank = PianoBank::new(44100.0);
+    bank.note_on(60, 5.0);
+    for _ in 0..4410 {
+        bank.go();
+    }
+    bank.note_off(60);
+    for _ in 0..88200 {
+        bank.go();
+    }
+    assert_eq!(0, bank.num_sounding());
+}
+
+#[test]
+fn piano_bank_sustain_work() {
+    let mut bank = PianoBank::new(44100.0);
+    bank.set_sustain_pedal(true);
+    bank.note_on(48, 5.0);
+    for _ in 0..4410 {
+        bank.go();
+    }
+    bank.note_off(48);
+    let mut peak_with_pedal: f32 = 0.0;
+    for _ in 0..22050 {
+        peak_with_pedal = peak_with_pedal.max(bank.go().abs());
+    }
+    // the released note and its sympathetic partners keep ringing
+    assert!(bank.is_sounding(48));
+    assert!(bank.num_sounding() > 1);
+
+    bank.set_sustain_pedal(false);
+    for _ in 0..44100 {
+        bank.go();
+    }
+    let mut peak_without_pedal: f32 = 0.0;
+    for _ in 0..4410 {
+        peak_without_pedal = peak_without_pedal.max(bank.go().abs());
+    }
+    assert!(peak_without_pedal < 0.01 * peak_with_pedal);
+}
+
+#[test]
+fn piano_bank_stereo_work() {
+    let mut bank = PianoBank::new(44100.0);
+    bank.note_on(36, 5.0);
+    let mut out = [0.0; 2];
+    let mut energy = [0.0; 2];
+    let mut correlation = 0.0;
+    for _ in 0..4410 {
+        bank.go_multi(&mut out);
+        energy[0] += out[0] * out[0];
+        energy[1] += out[1] * out[1];
+        correlation += out[0] * out[1];
+    }
+    // a bass note leans to the first channel, and the channels differ
+    assert!(energy[0] > energy[1]);
+    assert!(correlation < f32::sqrt(energy[0] * energy[1]) * 0.999);
+
+    // a new soundboard gets its pickups too
+    bank.set_soundboard(Box::new(ModalSoundboard::piano(44100.0)));
+    for _ in 0..100 {
+        bank.go_multi(&mut out);
+        assert!(out[0].is_finite() && out[1].is_finite());
+    }
+}
+
+#[test]
+fn piano_bank_hard_strike_work() {
+    let mut bank = PianoBank::new(44100.0);
+    let notes = [93, 102, 103, 104, 105, 106, 107, 108];
+    for &note in notes.iter() {
+        bank.note_on(note, 6.0);
+    }
+    let mut peak: f32 = 0.0;
+    for _ in 0..4410 {
+        let v = bank.go();
+        assert!(v.is_finite());
+        peak = peak.max(v.abs());
+    }
+    assert!(peak > 0.0 && peak < 1.0);
+}
@@ -0,0 +1,95 @@
+use super::error::Error;
+use super::midi::{Event, TimedEvent};
+use super::params::PianoParams;
+use super::piano_bank::PianoBank;
+
+// longest time rendered after the last event
+const RELEASE_TIME: f64 = 5.0;
+
+// MIDI velocity 1 to 127 as hammer velocity from about 0.5 to 6 m/s
+pub fn hammer_velocity(velocity: u8) -> f32 {
+    0.5 * f32::powf(12.0, velocity as f32 / 127.0)
+}
+
+fn apply(bank: &mut PianoBank, event: &Event) {
+    match *event {
+        Event::NoteOn { note, velocity } => bank.note_on(note as usize, hammer_velocity(velocity)),
+        Event::NoteOff { note } => bank.note_off(note as usize),
+        Event::SustainPedal(down) => bank.set_sustain_pedal(down),
+        Event::SoftPedal(down) => bank.set_soft_pedal(down),
+    }
+}
+
+// Events are applied at the sample nearest to their time.
+// Fails where the output is not finite.
+// Rendering stops once every note has died away after the last event.
+// With more than one channel the frames are interleaved.
+pub fn render(
+    events: &[TimedEvent],
+    sample_rate: u32,
+    nchannels: usize,
+    params: &PianoParams,
+) -> Result<Vec<f32>, Error> {
+    let mut bank = PianoBank::try_with_params(sample_rate as f32, params)?;
+    let sample_of = |time: f64| (time * sample_rate as f64).round() as usize;
+    let end = match events.last() {
+        Some(event) => sample_of(event.time + RELEASE_TIME),
+        None => 0,
+    };
+
+    let mut output = vec![];
+    let mut frame = vec![0.0; nchannels];
+    let mut next = 0;
+    for t in 0..end {
+        while next < events.len() && sample_of(events[next].time) <= t {
+            apply(&mut bank, &events[next].event);
+            next += 1;
+        }
+        if next == events.len() && bank.num_sounding() == 0 {
+            break;
+        }
+        if nchannels == 1 {
+            output.push(bank.go());
+        } else {
+            bank.go_multi(&mut frame);
+            output.extend_from_slice(&frame);
+        }
+        // rather than a file that goes silent where the output blew up
+        if !output[output.len() - nchannels..]
+            .iter()
+            .all(|v| v.is_finite())
+        {
+            return Err(Error::NotANumber("rendered sample"));
+        }
+    }
+    Ok(output)
+}
+
+#[test]
+fn render_work() {
+    let events = [
+        TimedEvent {
+            time: 0.0,
+            event: Event::NoteOn {
+                note: 72,
+                velocity: 100,
+            },
+        },
+        TimedEvent {
+            time: 0.1,
+            event: Event::NoteOff { note: 72 },
+        },
+    ];
+    let output = render(&events, 44100, 1, &PianoParams::default()).unwrap();
+    assert!(output.len() > 4410);
+    assert!(output.len() < 44100 * 5 + 4410);
+    assert!(output.iter().all(|v| v.is_finite()));
+    assert!(output.iter().any(|&v| v != 0.0));
+
+    // parameters that no key can be made with
+    let params = PianoParams {
+        hammer_position: 2.0,
+        ..PianoParams::default()
+    };
+    assert!(render(&events, 44100, 1, &params).is_err());
+}
@@ -0,0 +1,203 @@
+/*
+Frequency response over a grid of frequencies.
+
+H(e^{jw}) with w = 2 pi f / fs. The phase follows the convention arg H, so a delay of D samples
+has the phase -D w, the phase delay D and the group delay D.
+*/
+
+use num_complex::Complex;
+use num_traits::float::{Float, FloatConst};
+
+pub struct FrequencyResponse<T> {
+    pub frequencies: Vec<T>,
+    pub sample_frequency: T,
+    pub response: Vec<Complex<T>>,
+    // in samples
+    pub group_delay: Vec<T>,
+}
+
+// H(e^{jw}) of the polynomial c[0] + c[1] z^-1 + ... + c[n] z^-n
+pub fn polynomial_response<T: Float>(c: &[T], omega: T) -> Complex<T> {
+    let mut h = Complex::new(T::zero(), T::zero());
+    for (k, &c_k) in c.iter().enumerate() {
+        h = h + Complex::from_polar(c_k, -T::from(k).unwrap() * omega);
+    }
+    h
+}
+
+// Group delay in samples of the same polynomial, -d(arg)/dw = Re(sum k c[k] z^-k / sum c[k] z^-k).
+// Exact at any frequency, away from the zeros of the polynomial.
+pub fn polynomial_group_delay<T: Float>(c: &[T], omega: T) -> T {
+    let mut h = Complex::new(T::zero(), T::zero());
+    let mut dh = Complex::new(T::zero(), T::zero());
+    for (k, &c_k) in c.iter().enumerate() {
+        let term = Complex::from_polar(c_k, -T::from(k).unwrap() * omega);
+        h = h + term;
+        dh = dh + term * T::from(k).unwrap();
+    }
+    (dh / h).re
+}
+
+impl<T: Float + FloatConst> FrequencyResponse<T> {
+    // `h` is the response at a frequency in Hz
+    pub fn new<F: Fn(T) -> Complex<T>>(
+        frequencies: &[T],
+        sample_frequency: T,
+        h: F,
+    ) -> FrequencyResponse<T> {
+        let df: T = sample_frequency * T::from(1.0e-4).unwrap();
+        let response = frequencies.iter().map(|&f| h(f)).collect();
+        // the slope of the phase from the ratio of two close responses, which never wraps
+        let group_delay = frequencies
+            .iter()
+            .map(|&f| {
+                let ratio = h(f + df) / h(f - df);
+                -ratio.arg() / (Self::omega_of(df + df, sample_frequency))
+            })
+            .collect();
+        FrequencyResponse {
+            frequencies: frequencies.to_vec(),
+            sample_frequency,
+            response,
+            group_delay,
+        }
+    }
+
+    // with the group delay `tau` in samples at a frequency in Hz known in closed form
+    pub fn with_group_delay<F: Fn(T) -> Complex<T>, G: Fn(T) -> T>(
+        frequencies: &[T],
+        sample_frequency: T,
+        h: F,
+        tau: G,
+    ) -> FrequencyResponse<T> {
+        FrequencyResponse {
+            frequencies: frequencies.to_vec(),
+            sample_frequency,
+            response: frequencies.iter().map(|&f| h(f)).collect(),
+            group_delay: frequencies.iter().map(|&f| tau(f)).collect(),
+        }
+    }
+
+    fn omega_of(frequency: T, sample_frequency: T) -> T {
+        T::from(2).unwrap() * T::PI() * frequency / sample_frequency
+    }
+
+    pub fn omega(&self) -> Vec<T> {
+        self.frequencies
+            .iter()
+            .map(|&f| Self::omega_of(f, self.sample_frequency))
+            .collect()
+    }
+
+    pub fn magnitude(&self) -> Vec<T> {
+        self.response.iter().map(|h| h.norm()).collect()
+    }
+
+    pub fn magnitude_db(&self) -> Vec<T> {
+        self.response
+            .iter()
+            .map(|h| T::from(20).unwrap() * h.norm().log10())
+            .collect()
+    }
+
+    // Unwrapped phase in radians. Each point takes the branch nearest to the phase
+    // extrapolated with the group delay, starting from 0 at 0 Hz, so the grid may be coarse.
+    pub fn phase(&self) -> Vec<T> {
+        let two_pi = T::from(2).unwrap() * T::PI();
+        let mut phase = Vec::with_capacity(self.response.len());
+        let mut last_omega = T::zero();
+        let mut last_phase = T::zero();
+        let mut last_group_delay = self.group_delay.first().copied().unwrap_or(T::zero());
+        for ((h, &omega), &group_delay) in self
+            .response
+            .iter()
+            .zip(self.omega().iter())
+            .zip(self.group_delay.iter())
+        {
+            let half = T::from(0.5).unwrap();
+            let expected =
+                last_phase - (last_group_delay + group_delay) * half * (omega - last_omega);
+            let arg = h.arg();
+            let turns = ((expected - arg) / two_pi).round();
+            let unwrapped = arg + turns * two_pi;
+            phase.push(unwrapped);
+            last_omega = omega;
+            last_phase = unwrapped;
+            last_group_delay = group_delay;
+        }
+        phase
+    }
+
+    // in samples, with the group delay as its limit at 0 Hz
+    pub fn phase_delay(&self) -> Vec<T> {
+        self.phase()
+            .iter()
+            .zip(self.omega().iter())
+            .zip(self.group_delay.iter())
+            .map(|((&phase, &omega), &group_delay)| {
+                if omega == T::zero() {
+                    group_delay
+                } else {
+                    -phase / omega
+                }
+            })
+            .collect()
+    }
+
+    // the response of this system followed by `other` on the same grid
+    pub fn chain(&self, other: &FrequencyResponse<T>) -> FrequencyResponse<T> {
+        assert_eq!(self.frequencies.len(), other.frequencies.len());
+        FrequencyResponse {
+            frequencies: self.frequencies.clone(),
+            sample_frequency: self.sample_frequency,
+            response: self
+                .response
+                .iter()
+                .zip(other.response.iter())
+                .map(|(&x, &y)| x * y)
+                .collect(),
+            group_delay: self
+                .group_delay
+                .iter()
+                .zip(other.group_delay.iter())
+                .map(|(&x, &y)| x + y)
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn response_work() {
+    use super::filter::Filter;
+    use super::thirian::thirian;
+
+    let fs: f64 = 44100.0;
+    let frequencies: Vec<f64> = (1..20).map(|i| 1000.0 * i as f64).collect();
+
+    // a pure delay of 50 samples, far beyond one turn of phase
+    let pure_delay = |f: f64| Complex::from_polar(1.0, -50.0 * 2.0 * std::f64::consts::PI * f / fs);
+    let delay = FrequencyResponse::new(&frequencies, fs, pure_delay);
+    for (&d, &m) in delay.phase_delay().iter().zip(delay.magnitude_db().iter()) {
+        assert!((d - 50.0).abs() < 1.0e-6);
+        assert!(m.abs() < 1.0e-9);
+    }
+
+    // the Thiran allpass has the delay it is designed for at low frequencies
+    let filter: Filter<f64> = thirian(5.3, 5);
+    let response = filter.frequency_response(&frequencies[..2], fs);
+    for (&p, &g) in response
+        .phase_delay()
+        .iter()
+        .zip(response.group_delay.iter())
+    {
+        assert!((p - 5.3).abs() < 1.0e-2);
+        assert!((g - 5.3).abs() < 1.0e-2);
+    }
+
+    let chain = response.chain(&FrequencyResponse::new(&frequencies[..2], fs, pure_delay));
+    assert!((chain.phase_delay()[0] - 55.3).abs() < 1.0e-2);
+
+    // at 0 Hz the phase delay is the group delay
+    let response = filter.frequency_response(&[0.0, 1000.0], fs);
+    assert!((response.phase_delay()[0] - 5.3).abs() < 1.0e-2);
+}
@@ -13,17 +13,45 @@ impl<T: Clone> RingBuffer<T> {
         }
     }
 
-    pub fn iter(&self) -> RingBufferIter<T> {
+    pub fn iter(&self) -> RingBufferIter<'_, T> {
         RingBufferIter { buf: self, i: 0 }
     }
 
+    // index of the oldest element, avoiding `%` in the per-sample path
+    fn last_index(&self) -> usize {
+        if self.i == 0 {
+            self.n - 1
+        } else {
+            self.i - 1
+        }
+    }
+
     pub fn push(&mut self, x: T) {
-        self.v[(self.i + self.n - 1) % self.n] = x;
-        self.i = (self.i + self.n - 1) % self.n;
+        self.i = self.last_index();
+        self.v[self.i] = x;
     }
 
     pub fn last(&self) -> &T {
-        &self.v[(self.i + self.n - 1) % self.n]
+        &self.v[self.last_index()]
+    }
+
+    // the element pushed `k` pushes before the newest, 0 <= k < n
+    fn index(&self, k: usize) -> usize {
+        let j = self.i + k;
+        if j >= self.n {
+            j - self.n
+        } else {
+            j
+        }
+    }
+
+    pub fn get(&self, k: usize) -> &T {
+        &self.v[self.index(k)]
+    }
+
+    pub fn get_mut(&mut self, k: usize) -> &mut T {
+        let j = self.index(k);
+        &mut self.v[j]
     }
 
     // pub fn first(&self) -> &T {
@@ -42,7 +70,11 @@ impl<'a, T: Copy> Iterator for RingBufferIter<'a, T> {
         if self.i >= self.buf.n {
             None
         } else {
-            let ret: &T = &self.buf.v[(self.i + self.buf.i) % self.buf.n];
+            let mut j = self.i + self.buf.i;
+            if j >= self.buf.n {
+                j -= self.buf.n;
+            }
+            let ret: &T = &self.buf.v[j];
             self.i += 1;
             Some(ret)
         }
@@ -82,4 +114,9 @@ fn ring_buffer_work() {
     assert_eq!(None, iter.next());
 
     assert_eq!(&2, buf.last());
+
+    assert_eq!(&4, buf.get(0));
+    assert_eq!(&2, buf.get(2));
+    *buf.get_mut(1) = 5;
+    assert_eq!(Some(&5), buf.iter().nth(1));
 }
@@ -0,0 +1,331 @@
+/*
+Second-order sections
+
+A high order direct form filter loses its poles to rounding in f32.
+The same transfer function written as a cascade of biquads keeps every pole pair
+in its own section, which is well conditioned.
+
+The conversion finds the roots of the numerator and denominator of a `Filter` in f64,
+pairs complex conjugates, and gives each pole pair the nearest zeros.
+*/
+
+use num_complex::{Complex, Complex64};
+use num_traits::float::{Float, FloatConst};
+use num_traits::identities::Zero;
+
+use super::filter::Filter;
+use super::response::{polynomial_group_delay, polynomial_response, FrequencyResponse};
+
+// (b0 + b1 z^-1 + b2 z^-2) / (1 + a1 z^-1 + a2 z^-2) in transposed direct form II
+pub struct Biquad<T> {
+    pub b: [T; 3],
+    pub a: [T; 3],
+    s1: T,
+    s2: T,
+}
+
+impl<T: Float> Biquad<T> {
+    pub fn new(b: [T; 3], a: [T; 3]) -> Biquad<T> {
+        Biquad {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [T::one(), a[1] / a[0], a[2] / a[0]],
+            s1: T::zero(),
+            s2: T::zero(),
+        }
+    }
+
+    pub fn filter(&mut self, in_value: T) -> T {
+        let out_value = self.b[0] * in_value + self.s1;
+        self.s1 = self.b[1] * in_value - self.a[1] * out_value + self.s2;
+        self.s2 = self.b[2] * in_value - self.a[2] * out_value;
+        out_value
+    }
+
+    pub fn response(&self, omega: T) -> Complex<T> {
+        polynomial_response(&self.b, omega) / polynomial_response(&self.a, omega)
+    }
+
+    pub fn groupdelay(&self, omega: T) -> T {
+        polynomial_group_delay(&self.b, omega) - polynomial_group_delay(&self.a, omega)
+    }
+}
+
+pub struct SosFilter<T> {
+    pub sections: Vec<Biquad<T>>,
+    pub name: String,
+}
+
+// a first order factor c0 + c1 z^-1
+#[derive(Clone, Copy)]
+enum Factor {
+    // 1 - r z^-1
+    Root(Complex64),
+    // z^-1, a zero at infinity
+    Delay,
+}
+
+impl Factor {
+    fn position(&self) -> Complex64 {
+        match *self {
+            Factor::Root(r) => r,
+            Factor::Delay => Complex64::new(f64::INFINITY, 0.0),
+        }
+    }
+}
+
+// Roots of c[0] z^n + c[1] z^(n-1) + ... + c[n] by the Durand-Kerner method.
+// c[0] must not be zero.
+pub fn roots(c: &[f64]) -> Vec<Complex64> {
+    let n = c.len() - 1;
+    if n == 0 {
+        return vec![];
+    }
+    let monic: Vec<f64> = c.iter().map(|x| x / c[0]).collect();
+    // all roots lie within this radius
+    let radius = 1.0 + monic[1..].iter().fold(0.0, |m: f64, x| m.max(x.abs()));
+    let seed = Complex64::new(0.4, 0.9);
+    let mut z: Vec<Complex64> = (0..n)
+        .map(|k| seed.powu(k as u32) * radius / seed.norm().powi(k as i32))
+        .collect();
+
+    let evaluate = |x: Complex64| {
+        monic
+            .iter()
+            .fold(Complex64::new(0.0, 0.0), |acc, &m| acc * x + m)
+    };
+    for _ in 0..500 {
+        let mut change: f64 = 0.0;
+        for i in 0..n {
+            let mut denominator = Complex64::new(1.0, 0.0);
+            for j in 0..n {
+                if i != j {
+                    denominator *= z[i] - z[j];
+                }
+            }
+            let step = evaluate(z[i]) / denominator;
+            z[i] -= step;
+            change = change.max(step.norm() / (1.0 + z[i].norm()));
+        }
+        if change < 1.0e-15 {
+            break;
+        }
+    }
+    z
+}
+
+// Factors of c[0] + c[1] z^-1 + ... + c[n] z^-n, with the gain c[d] of its first nonzero term.
+fn factorize(c: &[f64]) -> (f64, Vec<Factor>) {
+    let d = c.iter().take_while(|&&x| x == 0.0).count();
+    if d == c.len() {
+        return (0.0, vec![]);
+    }
+    let mut factors = vec![Factor::Delay; d];
+    for r in roots(&c[d..]) {
+        factors.push(Factor::Root(r));
+    }
+    (c[d], factors)
+}
+
+// Groups factors into conjugate pairs and single real ones.
+fn group(factors: Vec<Factor>) -> Vec<Vec<Factor>> {
+    let tolerance = 1.0e-9;
+    let mut complex = vec![];
+    let mut real = vec![];
+    for factor in factors {
+        match factor {
+            Factor::Root(r) if r.im > tolerance => complex.push(factor),
+            Factor::Root(r) if r.im < -tolerance => {}
+            Factor::Root(r) => real.push(Factor::Root(Complex64::new(r.re, 0.0))),
+            Factor::Delay => real.push(factor),
+        }
+    }
+    let mut groups: Vec<Vec<Factor>> = complex
+        .into_iter()
+        .map(|factor| {
+            let r = factor.position();
+            vec![Factor::Root(r), Factor::Root(r.conj())]
+        })
+        .collect();
+    for pair in real.chunks(2) {
+        groups.push(pair.to_vec());
+    }
+    groups
+}
+
+// real coefficients of the product of the factors
+fn expand(factors: &[Factor]) -> [f64; 3] {
+    let mut c = [
+        Complex64::new(1.0, 0.0),
+        Complex64::new(0.0, 0.0),
+        Complex64::new(0.0, 0.0),
+    ];
+    for (k, factor) in factors.iter().enumerate() {
+        let (c0, c1) = match *factor {
+            Factor::Root(r) => (Complex64::new(1.0, 0.0), -r),
+            Factor::Delay => (Complex64::new(0.0, 0.0), Complex64::new(1.0, 0.0)),
+        };
+        for i in (1..(k + 2)).rev() {
+            c[i] = c[i] * c0 + c[i - 1] * c1;
+        }
+        c[0] *= c0;
+    }
+    [c[0].re, c[1].re, c[2].re]
+}
+
+impl<T: Float + Zero + FloatConst> SosFilter<T> {
+    pub fn new(sections: Vec<Biquad<T>>, name: String) -> SosFilter<T> {
+        SosFilter { sections, name }
+    }
+
+    // Factors in f64 and rounds only the sections to T, so a filter designed in f64
+    // keeps its poles until then.
+    pub fn from_filter<U: Float>(filter: &Filter<U>) -> SosFilter<T> {
+        let b: Vec<f64> = filter.b.iter().map(|x| x.to_f64().unwrap()).collect();
+        let a: Vec<f64> = filter.a.iter().map(|x| x.to_f64().unwrap()).collect();
+        let (b_gain, zeros) = factorize(&b);
+        let (a_gain, poles) = factorize(&a);
+
+        // poles nearest the unit circle take their zeros first
+        let mut pole_groups = group(poles);
+        pole_groups.sort_by(|x, y| {
+            let rx = x.iter().fold(0.0, |m: f64, f| m.max(f.position().norm()));
+            let ry = y.iter().fold(0.0, |m: f64, f| m.max(f.position().norm()));
+            ry.partial_cmp(&rx).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mut zero_groups = group(zeros);
+
+        let mut sections = vec![];
+        for poles in pole_groups.iter() {
+            let target = poles[0].position();
+            let nearest = (0..zero_groups.len()).min_by(|&i, &j| {
+                let di = (zero_groups[i][0].position() - target).norm();
+                let dj = (zero_groups[j][0].position() - target).norm();
+                di.partial_cmp(&dj).unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let zeros = match nearest {
+                Some(i) => zero_groups.remove(i),
+                None => vec![],
+            };
+            sections.push((expand(&zeros), expand(poles)));
+        }
+        // zeros left over when the denominator has lower degree
+        for zeros in zero_groups {
+            sections.push((expand(&zeros), [1.0, 0.0, 0.0]));
+        }
+        if sections.is_empty() {
+            sections.push(([1.0, 0.0, 0.0], [1.0, 0.0, 0.0]));
+        }
+
+        let gain = b_gain / a_gain;
+        let to_t = |c: [f64; 3]| {
+            [
+                T::from(c[0]).unwrap(),
+                T::from(c[1]).unwrap(),
+                T::from(c[2]).unwrap(),
+            ]
+        };
+        let sections = sections
+            .into_iter()
+            .enumerate()
+            .map(|(i, (b, a))| {
+                let b = if i == 0 {
+                    [gain * b[0], gain * b[1], gain * b[2]]
+                } else {
+                    b
+                };
+                Biquad::new(to_t(b), to_t(a))
+            })
+            .collect();
+        SosFilter::new(sections, filter.name.clone())
+    }
+
+    pub fn response(&self, frequency: T, sample_frequency: T) -> Complex<T> {
+        let omega: T = T::from(2).unwrap() * T::PI() * frequency / sample_frequency;
+        self.sections
+            .iter()
+            .fold(Complex::new(T::one(), T::zero()), |h, section| {
+                h * section.response(omega)
+            })
+    }
+
+    pub fn groupdelay(&self, frequency: T, sample_frequency: T) -> T {
+        let omega: T = T::from(2).unwrap() * T::PI() * frequency / sample_frequency;
+        self.sections
+            .iter()
+            .fold(T::zero(), |tau, section| tau + section.groupdelay(omega))
+    }
+
+    pub fn frequency_response(
+        &self,
+        frequencies: &[T],
+        sample_frequency: T,
+    ) -> FrequencyResponse<T> {
+        FrequencyResponse::with_group_delay(
+            frequencies,
+            sample_frequency,
+            |f| self.response(f, sample_frequency),
+            |f| self.groupdelay(f, sample_frequency),
+        )
+    }
+
+    pub fn filter(&mut self, in_value: T) -> T {
+        let mut x = in_value;
+        for section in self.sections.iter_mut() {
+            x = section.filter(x);
+        }
+        x
+    }
+}
+
+#[test]
+fn sos_work() {
+    use super::loss::loss;
+    use super::thirian::thirian;
+
+    // the same impulse response as the direct form in f64
+    let filters: Vec<Filter<f64>> = vec![thirian(5.3, 5), thirian(2.7, 2), loss(261.6, 0.25, 5.85)];
+    for mut filter in filters {
+        let mut sos = SosFilter::from_filter(&filter);
+        for n in 0..200 {
+            let x = if n == 0 { 1.0 } else { 0.0 };
+            let expected = filter.filter(x);
+            assert!((expected - sos.filter(x)).abs() < 1.0e-9);
+        }
+    }
+}
+
+#[test]
+fn sos_degenerate_work() {
+    // roots of NaN coefficients do not stop the conversion
+    let filter = Filter::new(
+        2,
+        vec![1.0, f64::NAN, 0.5],
+        vec![1.0, 0.0, 0.0],
+        String::new(),
+    );
+    let sos: SosFilter<f32> = SosFilter::from_filter(&filter);
+    assert!(!sos.sections.is_empty());
+}
+
+#[test]
+fn sos_high_order_work() {
+    use super::thirian::thirian;
+
+    // a high order Thiran allpass in f32 stays close to the f64 reference as sections
+    let d = 30.4;
+    let mut reference: Filter<f64> = thirian(d, 30);
+    let direct: Filter<f32> = thirian(d as f32, 30);
+    let mut sos: SosFilter<f32> = SosFilter::from_filter(&direct);
+    // designed in f64, only the sections are rounded
+    let mut sos_f64: SosFilter<f32> = SosFilter::from_filter(&reference);
+    let mut error: f64 = 0.0;
+    let mut error_f64: f64 = 0.0;
+    for n in 0..2000 {
+        let x = if n == 0 { 1.0 } else { 0.0 };
+        let expected = reference.filter(x);
+        error = error.max((expected - sos.filter(x as f32) as f64).abs());
+        error_f64 = error_f64.max((expected - sos_f64.filter(x as f32) as f64).abs());
+    }
+    assert!(error < 1.0e-3);
+    assert!(error_f64 < 1.0e-3);
+}
@@ -0,0 +1,246 @@
+/*
+The soundboard is seen from the strings through its admittance Y at the bridge.
+With the force F the strings exert on the bridge, the junction is
+
+dual_force_of_input = v \Sigma{Z_i} + F
+v = Y F
+
+A discrete admittance splits into a direct part and the response to past forces,
+v = y0 F + h, so
+
+F = (dual_force_of_input - h \Sigma{Z_i}) / (1 + y0 \Sigma{Z_i})
+*/
+
+pub trait Soundboard {
+    // Solves the junction at the bridge and returns the velocity there.
+    // `dual_force_of_input` and `string_impedance` are summed over all strings on the bridge.
+    fn go(&mut self, dual_force_of_input: f32, string_impedance: f32) -> f32;
+
+    // sound radiated for the last call of `go`
+    fn output(&self) -> f32;
+
+    // the real impedance the board approaches between and above its modes, where its admittance
+    // is least
+    fn impedance(&self) -> f32;
+
+    // Places pickups along the soundboard, from 0 at the bass side to 1 at the treble side.
+    fn set_pickups(&mut self, _positions: &[f32]) {}
+
+    // sound at one of the pickups for the last call of `go`
+    fn output_at(&self, _pickup: usize) -> f32 {
+        self.output()
+    }
+}
+
+pub struct ConstantImpedance {
+    impedance: f32,
+    v: f32,
+}
+
+impl ConstantImpedance {
+    pub fn new(impedance: f32) -> ConstantImpedance {
+        ConstantImpedance { impedance, v: 0.0 }
+    }
+}
+
+impl Soundboard for ConstantImpedance {
+    fn go(&mut self, dual_force_of_input: f32, string_impedance: f32) -> f32 {
+        self.v = dual_force_of_input / (string_impedance + self.impedance);
+        self.v
+    }
+
+    fn output(&self) -> f32 {
+        self.v
+    }
+
+    fn impedance(&self) -> f32 {
+        self.impedance
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Mode {
+    pub frequency: f32,
+    pub mass: f32,
+    pub q: f32,
+    pub radiation: f32,
+}
+
+// (1 / mass) s / (s^2 + (omega / q) s + omega^2), bilinear transformed
+// with prewarping at the mode frequency and run in transposed direct form II.
+struct ModeFilter {
+    b0: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    s1: f32,
+    s2: f32,
+    radiation: f32,
+    pickup_gains: Vec<f32>,
+}
+
+impl ModeFilter {
+    fn new(mode: &Mode, sample_rate: f32) -> ModeFilter {
+        let omega = 2.0 * std::f32::consts::PI * mode.frequency;
+        let c = omega / f32::tan(omega / (2.0 * sample_rate));
+        let damping = omega / mode.q;
+        let d0 = c * c + damping * c + omega * omega;
+        ModeFilter {
+            b0: c / (mode.mass * d0),
+            b2: -c / (mode.mass * d0),
+            a1: (2.0 * omega * omega - 2.0 * c * c) / d0,
+            a2: (c * c - damping * c + omega * omega) / d0,
+            s1: 0.0,
+            s2: 0.0,
+            radiation: mode.radiation,
+            pickup_gains: vec![],
+        }
+    }
+
+    fn update(&mut self, force: f32) -> f32 {
+        let v = self.b0 * force + self.s1;
+        self.s1 = -self.a1 * v + self.s2;
+        self.s2 = self.b2 * force - self.a2 * v;
+        v
+    }
+}
+
+pub struct ModalSoundboard {
+    modes: Vec<ModeFilter>,
+    impedance: f32,
+    v: f32,
+    output: f32,
+    outputs: Vec<f32>,
+}
+
+impl ModalSoundboard {
+    // `impedance` is the real impedance the board approaches between and above its modes
+    pub fn new(sample_rate: f32, impedance: f32, modes: &[Mode]) -> ModalSoundboard {
+        ModalSoundboard {
+            modes: modes
+                .iter()
+                .map(|mode| ModeFilter::new(mode, sample_rate))
+                .collect(),
+            impedance,
+            v: 0.0,
+            output: 0.0,
+            outputs: vec![],
+        }
+    }
+
+    // Modes spread from 80 Hz to 4 kHz with peaks a few times the board's admittance.
+    // Low modes radiate poorly as the board acts like a dipole there.
+    pub fn piano(sample_rate: f32) -> ModalSoundboard {
+        let impedance = 4000.0;
+        let nmodes = 24;
+        let mut modes = vec![];
+        for i in 0..nmodes {
+            let position = i as f32 / (nmodes - 1) as f32;
+            let frequency =
+                80.0 * f32::powf(4000.0 / 80.0, position) * (1.0 + 0.05 * f32::sin(2.3 * i as f32));
+            let q = 50.0 - 30.0 * position;
+            let peak_admittance = 4.0 / impedance;
+            modes.push(Mode {
+                frequency,
+                mass: q / (2.0 * std::f32::consts::PI * frequency * peak_admittance),
+                q,
+                radiation: frequency / (frequency + 200.0),
+            });
+        }
+        ModalSoundboard::new(sample_rate, impedance, &modes)
+    }
+}
+
+impl Soundboard for ModalSoundboard {
+    fn go(&mut self, dual_force_of_input: f32, string_impedance: f32) -> f32 {
+        let mut y0 = 1.0 / self.impedance;
+        let mut h = 0.0;
+        for mode in self.modes.iter() {
+            y0 += mode.b0;
+            h += mode.s1;
+        }
+        let force = (dual_force_of_input - h * string_impedance) / (1.0 + y0 * string_impedance);
+
+        self.v = force / self.impedance;
+        self.output = self.v;
+        for output in self.outputs.iter_mut() {
+            *output = self.v;
+        }
+        for mode in self.modes.iter_mut() {
+            let v = mode.update(force);
+            self.v += v;
+            self.output += mode.radiation * v;
+            for (output, gain) in self.outputs.iter_mut().zip(mode.pickup_gains.iter()) {
+                *output += gain * v;
+            }
+        }
+        self.v
+    }
+
+    fn output(&self) -> f32 {
+        self.output
+    }
+
+    fn impedance(&self) -> f32 {
+        self.impedance
+    }
+
+    // Each mode is picked up with the shape sin(pi (i + 1) x) of the i-th mode of a strip,
+    // scaled so that on average the pickups hear the modes as `output` does.
+    fn set_pickups(&mut self, positions: &[f32]) {
+        for (i, mode) in self.modes.iter_mut().enumerate() {
+            mode.pickup_gains = positions
+                .iter()
+                .map(|x| {
+                    mode.radiation
+                        * std::f32::consts::SQRT_2
+                        * f32::sin(std::f32::consts::PI * (i + 1) as f32 * x)
+                })
+                .collect();
+        }
+        self.outputs = vec![0.0; positions.len()];
+    }
+
+    // the mono output for a pickup that has not been set
+    fn output_at(&self, pickup: usize) -> f32 {
+        match self.outputs.get(pickup) {
+            Some(&output) => output,
+            None => self.output,
+        }
+    }
+}
+
+#[test]
+fn soundboard_constant_impedance_work() {
+    let mut soundboard = ConstantImpedance::new(4000.0);
+    assert_eq!(1.0, soundboard.go(4010.0, 10.0));
+    assert_eq!(1.0, soundboard.output());
+}
+
+#[test]
+fn soundboard_modal_work() {
+    // a single mode driven at its frequency meets the junction equation
+    // and decays after the drive stops
+    let mode = Mode {
+        frequency: 200.0,
+        mass: 1.0,
+        q: 20.0,
+        radiation: 1.0,
+    };
+    let mut soundboard = ModalSoundboard::new(44100.0, 4000.0, &[mode]);
+    let mut peak: f32 = 0.0;
+    for n in 0..4410 {
+        let drive = f32::sin(2.0 * std::f32::consts::PI * 200.0 * n as f32 / 44100.0);
+        let v = soundboard.go(drive, 5.0);
+        assert!(v.is_finite());
+        peak = peak.max(v.abs());
+    }
+    assert!(peak > 1.0 / 4005.0);
+
+    for _ in 0..44100 {
+        soundboard.go(0.0, 5.0);
+    }
+    let tail = soundboard.go(0.0, 5.0).abs();
+    assert!(tail < 1.0e-3 * peak);
+    assert_eq!(soundboard.output(), soundboard.output_at(3));
+}
@@ -1,31 +1,210 @@
-use super::filter::Filter;
+use num_complex::Complex;
+
+use super::error::Error;
+use super::filter::{max_gain, max_gain_by, Filter};
+use super::fractional_delay::{lagrange, window, FractionalDelay};
+use super::response::FrequencyResponse;
 use super::ring_buffer::RingBuffer;
+use super::sos::SosFilter;
+
+// order of the Lagrange interpolation of a modulated delay line, the same as a Farrow delay
+const INTERPOLATION_ORDER: usize = 3;
+// a modulated delay line can grow to this many times its size, an octave down
+pub const MAX_LENGTH_SCALE: usize = 2;
 
+// The filters run as second-order sections, which keep the long Thiran filters of the bass stable in f32.
 struct DelayLine {
+    size: usize,
     history_buffer: RingBuffer<f32>,
-    filters: Vec<Filter<f32>>,
+    capacity: usize,
+    // The delay in samples, `size` unless modulated. A modulated line reads its output between
+    // samples by Lagrange interpolation, whose taps follow the length smoothly.
+    length: f32,
+    // the length at a scale of 1, `size` unless the line ends between samples
+    rest_length: f32,
+    // of the filters and the fractional delay, which the taps count in the span of the line
+    filter_delay: f32,
+    // a ramp moves `length` by `length_step` a sample for `ramp` more samples to `target_length`
+    target_length: f32,
+    length_step: f32,
+    ramp: usize,
+    k0: usize,
+    h: [f32; INTERPOLATION_ORDER + 1],
+    // sum of squares of the last `size` samples
+    energy: f32,
+    filters: Vec<SosFilter<f32>>,
+    // after the filters
+    fractional_delay: Option<Box<dyn FractionalDelay>>,
 }
 
 impl DelayLine {
     fn new(size: usize, filters: Vec<Filter<f32>>) -> Self {
+        let capacity = MAX_LENGTH_SCALE * size + INTERPOLATION_ORDER + 1;
         DelayLine {
-            history_buffer: RingBuffer::<f32>::new(size, 0.0),
-            filters,
+            size,
+            history_buffer: RingBuffer::<f32>::new(capacity, 0.0),
+            capacity,
+            length: size as f32,
+            rest_length: size as f32,
+            filter_delay: 0.0,
+            target_length: size as f32,
+            length_step: 0.0,
+            ramp: 0,
+            k0: 0,
+            h: [0.0; INTERPOLATION_ORDER + 1],
+            energy: 0.0,
+            filters: filters.iter().map(SosFilter::from_filter).collect(),
+            fractional_delay: None,
+        }
+    }
+
+    // between 1 and the capacity, which holds MAX_LENGTH_SCALE times the size
+    fn clamp_length(&self, length: f32) -> f32 {
+        length
+            .max(1.0)
+            .min((self.capacity - INTERPOLATION_ORDER - 1) as f32)
+    }
+
+    fn set_length(&mut self, length: f32) {
+        self.length = self.clamp_length(length);
+        self.target_length = self.length;
+        self.ramp = 0;
+        self.update_taps();
+    }
+
+    // reaches `length` linearly in `samples` samples
+    fn ramp_length(&mut self, length: f32, samples: usize) {
+        self.target_length = self.clamp_length(length);
+        if samples == 0 {
+            self.set_length(self.target_length);
+            return;
         }
+        self.length_step = (self.target_length - self.length) / samples as f32;
+        self.ramp = samples;
+    }
+
+    fn update_taps(&mut self) {
+        // the sample `length` pushes back is `length - 1` behind the newest, as this reads before pushing
+        let (k0, d) = window(INTERPOLATION_ORDER, self.length - 1.0);
+        self.k0 = k0;
+        lagrange(d, &mut self.h);
     }
 
     fn do_delay(&mut self, input: f32) -> f32 {
-        let mut x: f32 = *self.history_buffer.last();
+        if self.ramp > 0 {
+            self.ramp -= 1;
+            self.length = if self.ramp == 0 {
+                self.target_length
+            } else {
+                self.length + self.length_step
+            };
+            self.update_taps();
+        }
+
+        let mut x: f32 = if self.length == self.size as f32 {
+            *self.history_buffer.get(self.size - 1)
+        } else {
+            let mut y = 0.0;
+            for k in 0..(INTERPOLATION_ORDER + 1) {
+                y += self.h[k] * self.history_buffer.get(self.k0 + k);
+            }
+            y
+        };
+        let leaving = *self.history_buffer.get(self.size - 1);
+        self.energy += input * input - leaving * leaving;
 
         let filter_num = self.filters.len();
         for i in 0..filter_num {
             x = self.filters[i].filter(x);
         }
+        if let Some(fractional_delay) = self.fractional_delay.as_mut() {
+            x = fractional_delay.process(x);
+        }
 
         self.history_buffer.push(input);
 
         x
     }
+
+    // samples from the input to where the wave leaves the line, through the filters
+    fn span(&self) -> f32 {
+        self.rest_length - 1.0 + self.filter_delay
+    }
+
+    // Taps at `travel` samples from the input, between 0 and size - 1, by linear interpolation.
+    // The filters are lumped at the output, so the taps see the wave before them.
+    fn tap(&self, travel: f32) -> (usize, f32) {
+        let travel = travel.max(0.0).min((self.size - 1) as f32);
+        let k = (travel as usize).min(self.size.saturating_sub(2));
+        (k, travel - k as f32)
+    }
+
+    fn read(&self, travel: f32) -> f32 {
+        let (k, frac) = self.tap(travel);
+        if self.size == 1 {
+            return *self.history_buffer.get(0);
+        }
+        (1.0 - frac) * self.history_buffer.get(k) + frac * self.history_buffer.get(k + 1)
+    }
+
+    fn write(&mut self, travel: f32, value: f32) {
+        let (k, frac) = self.tap(travel);
+        if self.size == 1 {
+            self.add(0, value);
+            return;
+        }
+        self.add(k, (1.0 - frac) * value);
+        self.add(k + 1, frac * value);
+    }
+
+    fn add(&mut self, k: usize, value: f32) {
+        let x = self.history_buffer.get_mut(k);
+        let before = *x;
+        *x += value;
+        self.energy += *x * *x - before * before;
+    }
+
+    // mean square of the travelling wave along the line
+    fn mean_square(&self) -> f32 {
+        self.energy.max(0.0) / self.size as f32
+    }
+
+    // the delay, at the end of a ramp, followed by the filters and the fractional delay
+    fn frequency_response(
+        &self,
+        frequencies: &[f32],
+        sample_frequency: f32,
+    ) -> FrequencyResponse<f32> {
+        let delay = self.target_length;
+        FrequencyResponse::with_group_delay(
+            frequencies,
+            sample_frequency,
+            |f| {
+                let omega = 2.0 * std::f32::consts::PI * f / sample_frequency;
+                let h = self
+                    .filters
+                    .iter()
+                    .fold(Complex::from_polar(1.0, -delay * omega), |h, filter| {
+                        h * filter.response(f, sample_frequency)
+                    });
+                match self.fractional_delay.as_ref() {
+                    Some(fractional_delay) => h * fractional_delay.response(f, sample_frequency),
+                    None => h,
+                }
+            },
+            |f| {
+                let tau = self.filters.iter().fold(delay, |tau, filter| {
+                    tau + filter.groupdelay(f, sample_frequency)
+                });
+                match self.fractional_delay.as_ref() {
+                    Some(fractional_delay) => {
+                        tau + fractional_delay.groupdelay(f, sample_frequency)
+                    }
+                    None => tau,
+                }
+            },
+        )
+    }
 }
 
 pub struct String {
@@ -37,6 +216,9 @@ pub struct String {
     to_right_delay_line: DelayLine,
 }
 
+// a passive loop filter chain has |H| <= 1, up to the rounding of f32
+const MAX_LOOP_GAIN: f32 = 1.0 + 1.0e-4;
+
 impl String {
     pub fn new(
         del1: usize,
@@ -44,6 +226,10 @@ impl String {
         left_filters: Vec<Filter<f32>>,
         right_filters: Vec<Filter<f32>>,
     ) -> String {
+        debug_assert!(
+            max_gain(&left_filters) <= MAX_LOOP_GAIN && max_gain(&right_filters) <= MAX_LOOP_GAIN,
+            "loop filter gain exceeds unity"
+        );
         String {
             v_at_left_to_left: 0.0,
             v_at_right_to_left: 0.0,
@@ -54,8 +240,291 @@ impl String {
         }
     }
 
+    // Rejects empty delay lines, unstable filters and loop gains above unity.
+    // The fractional delay goes at the end of the line to the right, with its filters.
+    pub fn try_new(
+        del1: usize,
+        del2: usize,
+        left_filters: Vec<Filter<f32>>,
+        right_filters: Vec<Filter<f32>>,
+        fractional_delay: Option<Box<dyn FractionalDelay>>,
+    ) -> Result<String, Error> {
+        if del1 == 0 || del2 == 0 {
+            return Err(Error::InvalidParameter("string delay"));
+        }
+        let checked = |filters: Vec<Filter<f32>>| {
+            filters
+                .into_iter()
+                .map(|filter| Filter::try_new(filter.n, filter.a, filter.b, filter.name))
+                .collect::<Result<Vec<Filter<f32>>, Error>>()
+        };
+        let left_filters = checked(left_filters)?;
+        let right_filters = checked(right_filters)?;
+        if let Some(fractional_delay) = fractional_delay.as_ref() {
+            if !fractional_delay.is_stable() {
+                return Err(Error::UnstableFilter(std::string::String::from(
+                    "fractional delay",
+                )));
+            }
+        }
+        let left_gain = max_gain(&left_filters);
+        let right_gain = max_gain_by(|f| {
+            let f = f as f32;
+            let gain = right_filters
+                .iter()
+                .fold(1.0, |g, filter| g * filter.response(f, 1.0).norm());
+            match fractional_delay.as_ref() {
+                Some(fractional_delay) => gain * fractional_delay.response(f, 1.0).norm(),
+                None => gain,
+            }
+        });
+        for &gain in [left_gain, right_gain].iter() {
+            if gain > MAX_LOOP_GAIN {
+                return Err(Error::LoopGain(gain));
+            }
+        }
+        let mut string = String::new(del1, del2, left_filters, right_filters);
+        if let Some(fractional_delay) = fractional_delay {
+            string.set_fractional_delay(fractional_delay);
+        }
+        Ok(string)
+    }
+
+    pub fn to_left_response(
+        &self,
+        frequencies: &[f32],
+        sample_frequency: f32,
+    ) -> FrequencyResponse<f32> {
+        self.to_left_delay_line
+            .frequency_response(frequencies, sample_frequency)
+    }
+
+    pub fn to_right_response(
+        &self,
+        frequencies: &[f32],
+        sample_frequency: f32,
+    ) -> FrequencyResponse<f32> {
+        self.to_right_delay_line
+            .frequency_response(frequencies, sample_frequency)
+    }
+
+    // tunes the string with a fractional delay at the end of the line to the right
+    pub fn set_fractional_delay(&mut self, fractional_delay: Box<dyn FractionalDelay>) {
+        self.to_right_delay_line.fractional_delay = Some(fractional_delay);
+    }
+
+    // in samples, the mean of both directions without the filters
+    pub fn length(&self) -> f32 {
+        0.5 * (self.to_left_delay_line.rest_length + self.to_right_delay_line.rest_length)
+    }
+
+    // Ends both delay lines `length` samples after their input, between 2 and their size, for a
+    // string whose length falls between samples. The lines interpolate their output.
+    pub fn set_rest_length(&mut self, length: f32) {
+        for line in [&mut self.to_left_delay_line, &mut self.to_right_delay_line] {
+            line.rest_length = length.max(2.0).min(line.size as f32);
+            line.set_length(line.rest_length);
+        }
+    }
+
+    // The delays of the filters of each direction, in samples, for the taps to read both
+    // travelling waves at the same point of the string.
+    pub fn set_filter_delays(&mut self, to_left: f32, to_right: f32) {
+        self.to_left_delay_line.filter_delay = to_left;
+        self.to_right_delay_line.filter_delay = to_right;
+    }
+
+    // in samples, the mean of both directions from one end of the string to the other,
+    // through the filters
+    pub fn span(&self) -> f32 {
+        0.5 * (self.to_left_delay_line.span() + self.to_right_delay_line.span())
+    }
+
+    // Scales both delay lines to `scale` times their rest length from the next sample on.
+    // Scales above MAX_LENGTH_SCALE are held at MAX_LENGTH_SCALE, the most the lines hold.
+    pub fn set_length_scale(&mut self, scale: f32) {
+        for line in [&mut self.to_left_delay_line, &mut self.to_right_delay_line] {
+            let length = scale * line.rest_length;
+            line.set_length(length);
+        }
+    }
+
+    // `set_length_scale` reached linearly over `samples` samples, for pitch glides and
+    // tension modulation, so that a step of the scale does not jump the read position
+    pub fn ramp_length_scale(&mut self, scale: f32, samples: usize) {
+        for line in [&mut self.to_left_delay_line, &mut self.to_right_delay_line] {
+            let length = scale * line.rest_length;
+            line.ramp_length(length, samples);
+        }
+    }
+
+    // mean square of the velocity of the travelling waves
+    pub fn mean_square(&self) -> f32 {
+        let left = &self.to_left_delay_line;
+        let right = &self.to_right_delay_line;
+        (left.mean_square() * left.size as f32 + right.mean_square() * right.size as f32)
+            / (left.size + right.size) as f32
+    }
+
+    // A position runs from 0 at the left end to 1 at the right end of the span. The filters are
+    // lumped at the output of each line, so a wave between them reads at the end of its line.
+    fn travel(&self, position: f32) -> (f32, f32) {
+        let x = position * self.span();
+        (self.span() - x, x)
+    }
+
+    // the travelling waves at `position`, to the left and to the right
+    pub fn read_waves(&self, position: f32) -> (f32, f32) {
+        let (to_left, to_right) = self.travel(position);
+        (
+            self.to_left_delay_line.read(to_left),
+            self.to_right_delay_line.read(to_right),
+        )
+    }
+
+    // velocity at `position`, the sum of both travelling waves
+    pub fn read(&self, position: f32) -> f32 {
+        let (to_left, to_right) = self.read_waves(position);
+        to_left + to_right
+    }
+
+    // adds `value` to the velocity at `position`, half to each travelling wave
+    pub fn write(&mut self, position: f32, value: f32) {
+        let (to_left, to_right) = self.travel(position);
+        self.to_left_delay_line.write(to_left, 0.5 * value);
+        self.to_right_delay_line.write(to_right, 0.5 * value);
+    }
+
     pub fn do_delay(&mut self) {
         self.v_at_left_to_left = self.to_left_delay_line.do_delay(self.v_at_right_to_left);
         self.v_at_right_to_right = self.to_right_delay_line.do_delay(self.v_at_left_to_right);
     }
 }
+
+#[test]
+fn string_response_work() {
+    use super::thirian::thirian;
+
+    let string = String::new(10, 20, vec![thirian(2.5, 2)], vec![]);
+    let frequencies = [100.0, 200.0];
+    let left = string.to_left_response(&frequencies, 44100.0);
+    let right = string.to_right_response(&frequencies, 44100.0);
+    for (&l, &r) in left.phase_delay().iter().zip(right.phase_delay().iter()) {
+        assert!((l - 12.5).abs() < 1.0e-2);
+        assert!((r - 20.0).abs() < 1.0e-2);
+    }
+}
+
+#[test]
+fn string_loop_gain_work() {
+    use super::loss::loss;
+
+    let passive = loss(261.6_f32, 0.25, 5.85);
+    assert!(String::try_new(10, 20, vec![], vec![passive], None).is_ok());
+    let amplifying = Filter::new(
+        1,
+        vec![1.0, -0.5],
+        vec![0.6, 0.0],
+        std::string::String::new(),
+    );
+    assert!(matches!(
+        String::try_new(10, 20, vec![amplifying], vec![], None),
+        Err(Error::LoopGain(_))
+    ));
+
+    // the fractional delay is part of the loop
+    use super::fractional_delay::{LagrangeDelay, ThiranDelay};
+    let passive = loss(261.6_f32, 0.25, 5.85);
+    let thiran: Box<dyn FractionalDelay> = Box::new(ThiranDelay::new(3.4));
+    assert!(String::try_new(10, 20, vec![], vec![passive], Some(thiran)).is_ok());
+    // a Lagrange interpolator outside its central window amplifies high frequencies
+    let lagrange: Box<dyn FractionalDelay> = Box::new(LagrangeDelay::new(3, 0.2));
+    assert!(matches!(
+        String::try_new(10, 20, vec![], vec![], Some(lagrange)),
+        Err(Error::LoopGain(_))
+    ));
+}
+
+#[test]
+fn string_tap_work() {
+    // a pluck in the middle splits into two waves that meet the ends together
+    let mut string = String::new(11, 11, vec![], vec![]);
+    string.write(0.5, 1.0);
+    assert!((string.read(0.5) - 1.0).abs() < 1.0e-6);
+    assert_eq!(0.0, string.read(0.0));
+    for _ in 0..5 {
+        string.do_delay();
+        assert_eq!(0.0, string.v_at_left_to_left);
+    }
+    string.do_delay();
+    assert_eq!(0.5, string.v_at_left_to_left);
+    assert_eq!(0.5, string.v_at_right_to_right);
+
+    // a write between samples is read back in full at its two neighbours
+    let mut string = String::new(11, 11, vec![], vec![]);
+    string.write(0.25, 1.0);
+    let sum: f32 = (0..11).map(|i| string.read(i as f32 / 10.0)).sum();
+    assert!((sum - 1.0).abs() < 1.0e-6);
+}
+
+#[test]
+fn string_span_work() {
+    // a pluck meets both ends together where the filters delay the shorter line
+    let mut string = String::new(11, 15, vec![], vec![]);
+    string.set_filter_delays(4.0, 0.0);
+    assert_eq!(14.0, string.span());
+    string.write(0.5, 1.0);
+    let mut to_left_arrival = 0;
+    let mut to_right_arrival = 0;
+    for n in 1..20 {
+        string.do_delay();
+        if string.v_at_left_to_left != 0.0 {
+            to_left_arrival = n;
+        }
+        if string.v_at_right_to_right != 0.0 {
+            to_right_arrival = n;
+        }
+    }
+    assert_eq!(to_right_arrival, to_left_arrival + 4);
+}
+
+#[test]
+fn string_length_work() {
+    // a pulse takes the modulated length to cross the line
+    let mut string = String::new(20, 20, vec![], vec![]);
+    string.set_length_scale(1.5);
+    string.v_at_left_to_right = 1.0;
+    let mut output = vec![];
+    for _ in 0..40 {
+        string.do_delay();
+        string.v_at_left_to_right = 0.0;
+        output.push(string.v_at_right_to_right);
+    }
+    let arrival = output
+        .iter()
+        .enumerate()
+        .fold(0.0, |t, (n, &y)| t + n as f32 * y);
+    assert!((arrival - 30.0).abs() < 1.0e-4);
+    assert!((string.to_right_response(&[100.0], 44100.0).group_delay[0] - 30.0).abs() < 1.0e-2);
+
+    // an integer scale reads the samples as they are
+    string.set_length_scale(1.0);
+    string.v_at_left_to_right = 1.0;
+    string.do_delay();
+    string.v_at_left_to_right = 0.0;
+    for _ in 0..19 {
+        string.do_delay();
+    }
+    assert!((string.mean_square() - 0.5 / 20.0).abs() < 1.0e-6);
+    string.do_delay();
+    assert_eq!(1.0, string.v_at_right_to_right);
+
+    // a ramp reaches the length after its samples, and moves the read position a little each sample
+    string.ramp_length_scale(1.5, 10);
+    assert!((string.to_right_response(&[100.0], 44100.0).group_delay[0] - 30.0).abs() < 1.0e-2);
+    for n in 0..10 {
+        assert!((string.to_right_delay_line.length - (20.0 + n as f32)).abs() < 1.0e-4);
+        string.do_delay();
+    }
+    assert_eq!(30.0, string.to_right_delay_line.length);
+}
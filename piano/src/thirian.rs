@@ -48,7 +48,14 @@ fn db<T: Float>(b: T, f: T, m: usize) -> T {
 pub fn thirian<T: Float + Zero + FloatConst>(d: T, n: usize) -> Filter<T> {
     let mut a = vec![T::zero(); n + 1];
     let mut b = vec![T::zero(); n + 1];
+    thirian_coefficients(d, &mut a, &mut b);
 
+    Filter::new(n, a, b, String::from("thirian"))
+}
+
+// the coefficients of `thirian` of the order a.len() - 1, in place
+pub fn thirian_coefficients<T: Float>(d: T, a: &mut [T], b: &mut [T]) {
+    let n = a.len() - 1;
     for k in 0..n + 1 {
         let mut ak: T = T::from(combinations(n as u64, k as u64)).unwrap();
         if k % 2 == 1 {
@@ -61,8 +68,6 @@ pub fn thirian<T: Float + Zero + FloatConst>(d: T, n: usize) -> Filter<T> {
         a[k] = ak;
         b[n - k] = ak;
     }
-
-    Filter::new(n, a, b, String::from("thirian"))
 }
 
 pub fn thirian_dispersion<T: Float + Zero + FloatConst>(b: T, f: T, m: usize) -> Filter<T> {
@@ -81,12 +86,13 @@ pub fn thirian_dispersion<T: Float + Zero + FloatConst>(b: T, f: T, m: usize) ->
 }
 
 #[test]
+#[allow(non_snake_case)]
 fn thirian_dispersion_work() {
     let B: f64 = 0.000175;
     let f: f64 = 261.520935;
     let M: usize = 4;
 
-    let mut filter = thirian_dispersion(B, f, M);
+    let filter = thirian_dispersion(B, f, M);
 
     assert_eq!(filter.a[0], 1.000000);
     assert_eq!(filter.a[1], -1.2356172436816146);
@@ -95,12 +101,13 @@ fn thirian_dispersion_work() {
     assert_eq!(filter.b[1], -1.2356172436816146);
     assert_eq!(filter.b[2], 1.000000);
 
-    assert_eq!(filter.groupdelay(261.520935, 44100.0), 6.849290787197244);
+    assert_eq!(filter.groupdelay(261.520935, 44100.0), 6.849290917558195);
 }
 
 #[test]
+#[allow(non_snake_case)]
 fn thirian_work() {
     let D: f64 = 6.1712799072265625;
 
-    let mut filter = thirian(D, D as usize);
+    let _filter = thirian(D, D as usize);
 }